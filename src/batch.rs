@@ -0,0 +1,355 @@
+use crate::config::Config;
+use crate::encoding::{decode, SourceEncoding};
+use crate::write_output;
+use update_file_core::OperationManager;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// The leading directories of a glob pattern that contain no wildcard.
+fn glob_root(pattern: &str) -> PathBuf {
+    let mut root = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        if is_glob(&component.as_os_str().to_string_lossy()) {
+            break;
+        }
+        root.push(component);
+    }
+    root
+}
+
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk_dir(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Files matched by `source`, a file, a directory or a glob pattern, each with
+/// its path relative to the directory it was found in. Files inside
+/// `output_dir` are left out, so running a batch twice does not pick up its own
+/// output.
+pub fn collect_sources(
+    source: &str,
+    output_dir: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>, io::Error> {
+    let (root, mut files) = if Path::new(source).is_dir() {
+        let mut files = Vec::new();
+        walk_dir(Path::new(source), &mut files)?;
+        (PathBuf::from(source), files)
+    } else if is_glob(source) {
+        let paths = glob::glob(source)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let mut files = Vec::new();
+        for path in paths {
+            let path = path.map_err(io::Error::from)?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        (glob_root(source), files)
+    } else {
+        let path = PathBuf::from(source);
+        let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        (root, vec![path])
+    };
+    files.sort();
+
+    let output_dir = fs::canonicalize(output_dir).ok();
+    let mut sources = Vec::new();
+    for path in files {
+        if let (Some(output_dir), Ok(canonical)) = (&output_dir, fs::canonicalize(&path)) {
+            if canonical.starts_with(output_dir) {
+                continue;
+            }
+        }
+        let relative = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+        sources.push((path, relative));
+    }
+    Ok(sources)
+}
+
+/// Outcome of a batch run.
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    /// Source files that failed, with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Batch finished: {} succeeded, {} failed",
+            self.succeeded,
+            self.failed.len()
+        )?;
+        for (path, error) in &self.failed {
+            writeln!(f, "  {}: {}", path.display(), error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the whole pipeline on one file and write its output.
+fn run_file(mut opt_manager: OperationManager, config: &Config) -> Result<(), Box<dyn Error>> {
+    let (content, codec) = decode(&fs::read(&config.source_path)?, config.encoding)?;
+    let result = opt_manager.run_all_operations(&content, &config.runner)?;
+    let new_content = codec.encode(&result.new_content)?;
+    if let Some(parent) = Path::new(&config.output_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_output(config, &new_content)?;
+    Ok(())
+}
+
+/// Run the pipeline on every file of `config.source_path`, writing each output
+/// to the same relative path under `config.output_path`. Up to `config.jobs`
+/// files run at the same time, and a failing file does not stop the others.
+/// The summary lists the files in source order, however the jobs finished.
+pub fn run_batch(config: &Config) -> Result<BatchSummary, io::Error> {
+    let sources = collect_sources(&config.source_path, &config.output_path)?;
+    if sources.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No source files found in {}", config.source_path),
+        ));
+    }
+    let mut opt_manager = OperationManager::new(&config.opt_dir);
+    opt_manager.set_execution_mode(config.execution_mode);
+    opt_manager.set_limits(config.limits);
+    opt_manager.set_sandboxed(config.sandboxed);
+    opt_manager.set_binary(config.encoding == SourceEncoding::Binary);
+
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let jobs = (0..config.jobs.clamp(1, sources.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((source, relative)) = sources.get(index) else {
+                            break;
+                        };
+                        let file_config = Config {
+                            source_path: source.to_string_lossy().into_owned(),
+                            output_path: Path::new(&config.output_path)
+                                .join(relative)
+                                .to_string_lossy()
+                                .into_owned(),
+                            ..config.clone()
+                        };
+                        // every file gets its own manager, so no data map is shared
+                        let result =
+                            run_file(opt_manager.clone(), &file_config).map_err(|e| e.to_string());
+                        results.push((index, result));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+        jobs.into_iter()
+            .flat_map(|job| job.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut summary = BatchSummary::default();
+    for (index, result) in results {
+        match result {
+            Ok(()) => summary.succeeded += 1,
+            Err(e) => summary.failed.push((sources[index].0.clone(), e)),
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, OutputPolicy};
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_glob_root() {
+        assert_eq!(glob_root("configs/**/*.toml"), Path::new("configs"));
+        assert_eq!(glob_root("a/b/c?.toml"), Path::new("a/b"));
+        assert_eq!(glob_root("*.toml"), Path::new(""));
+    }
+
+    #[test]
+    fn test_collect_sources() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        write(&source_dir.join("a.toml"), "a");
+        write(&source_dir.join("sub/b.toml"), "b");
+        write(&source_dir.join("sub/c.txt"), "c");
+        write(&source_dir.join("out/a.toml"), "a");
+        let source = source_dir.to_str().unwrap();
+        let output = source_dir.join("out");
+        let relative = |sources: Vec<(PathBuf, PathBuf)>| {
+            sources
+                .into_iter()
+                .map(|(_, relative)| relative)
+                .collect::<Vec<_>>()
+        };
+
+        let sources = collect_sources(source, output.to_str().unwrap()).unwrap();
+        assert_eq!(sources[0].0, source_dir.join("a.toml"));
+        assert_eq!(
+            relative(sources),
+            [
+                Path::new("a.toml"),
+                Path::new("sub/b.toml"),
+                Path::new("sub/c.txt")
+            ]
+        );
+
+        let pattern = format!("{}/**/*.toml", source);
+        let sources = collect_sources(&pattern, output.to_str().unwrap()).unwrap();
+        assert_eq!(
+            relative(sources),
+            [Path::new("a.toml"), Path::new("sub/b.toml")]
+        );
+
+        let file = source_dir.join("sub/b.toml");
+        let sources = collect_sources(file.to_str().unwrap(), "out").unwrap();
+        assert_eq!(relative(sources), [Path::new("b.toml")]);
+    }
+
+    #[test]
+    fn test_run_batch() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let output_dir = temp_dir.path().join("output");
+        write(&source_dir.join("a.txt"), "Hello");
+        write(&source_dir.join("sub/b.txt"), "World");
+        // the test operations do not consume multi-line content fully
+        write(&source_dir.join("sub/c.txt"), "Hello\nWorld");
+        let config = test_config(
+            "update-file-core/src/tests",
+            source_dir.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+        );
+
+        let summary = run_batch(&config).unwrap();
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, source_dir.join("sub/c.txt"));
+        assert_eq!(
+            fs::read_to_string(output_dir.join("a.txt")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            fs::read_to_string(output_dir.join("sub/b.txt")).unwrap(),
+            "world\n"
+        );
+        assert!(!output_dir.join("sub/c.txt").exists());
+        assert!(summary
+            .to_string()
+            .starts_with("Batch finished: 2 succeeded, 1 failed\n"));
+
+        // existing outputs are refused like a single run
+        let summary = run_batch(&config).unwrap();
+        assert_eq!(summary.succeeded, 0);
+        assert_eq!(summary.failed.len(), 3);
+    }
+
+    #[test]
+    fn test_run_batch_jobs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let output_dir = temp_dir.path().join("output");
+        for i in 0..8 {
+            let content = if i % 3 == 0 {
+                format!("File\n{}", i)
+            } else {
+                format!("File {}", i)
+            };
+            write(&source_dir.join(format!("{}.txt", i)), &content);
+        }
+        let config = Config {
+            jobs: 4,
+            ..test_config(
+                "update-file-core/src/tests",
+                source_dir.to_str().unwrap(),
+                output_dir.to_str().unwrap(),
+            )
+        };
+
+        let summary = run_batch(&config).unwrap();
+        assert_eq!(summary.succeeded, 5);
+        let failed = summary
+            .failed
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(failed, ["0.txt", "3.txt", "6.txt"]);
+        for i in [1, 2, 4, 5, 7] {
+            assert_eq!(
+                fs::read_to_string(output_dir.join(format!("{}.txt", i))).unwrap(),
+                format!("file {}\n", i)
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_batch_encoding() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let output_dir = temp_dir.path().join("output");
+        write(&source_dir.join("a.txt"), "CAF\u{c9}");
+        fs::write(source_dir.join("b.txt"), b"\xff\xfeC\x00\xc9\x00").unwrap();
+        let mut config = test_config(
+            "update-file-core/src/tests",
+            source_dir.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+        );
+
+        // every file is written back in its own encoding
+        let summary = run_batch(&config).unwrap();
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(
+            fs::read(output_dir.join("a.txt")).unwrap(),
+            "caf\u{e9}\n".as_bytes()
+        );
+        assert_eq!(
+            fs::read(output_dir.join("b.txt")).unwrap(),
+            b"\xff\xfec\x00\xe9\x00\n\x00"
+        );
+
+        fs::remove_dir_all(&source_dir).unwrap();
+        fs::create_dir_all(&source_dir).unwrap();
+        fs::write(source_dir.join("c.txt"), b"CAF\xc9").unwrap();
+        config.encoding = "latin1".parse().unwrap();
+        let summary = run_batch(&config).unwrap();
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(fs::read(output_dir.join("c.txt")).unwrap(), b"caf\xe9\n");
+
+        // the same file is refused as UTF-8
+        config.encoding = SourceEncoding::Text(encoding_rs::UTF_8);
+        config.output_policy = OutputPolicy::Overwrite;
+        let summary = run_batch(&config).unwrap();
+        assert_eq!(summary.failed.len(), 1);
+    }
+}
@@ -1,3 +1,15 @@
+use crate::encoding::SourceEncoding;
+use update_file_core::{ExecutionMode, Limits};
+
+/// What to do when the output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputPolicy {
+    Refuse,
+    Overwrite,
+    Backup,
+}
+
+#[derive(Clone)]
 pub struct Config {
     pub editor: String,
     pub viewer: String,
@@ -5,4 +17,37 @@ pub struct Config {
     pub opt_dir: String,
     pub source_path: String,
     pub output_path: String,
+    pub output_policy: OutputPolicy,
+    /// Appended to the output path to name its backup.
+    pub backup_suffix: String,
+    pub execution_mode: ExecutionMode,
+    /// Number of files a batch runs at the same time.
+    pub jobs: usize,
+    /// Limits of the operations without their own in the manifest.
+    pub limits: Limits,
+    /// Whether the operations run in a sandbox.
+    pub sandboxed: bool,
+    /// How source files are decoded, and the outputs encoded back.
+    pub encoding: SourceEncoding,
+}
+
+/// A config running the operations of `opt_dir` on `source_path` with the
+/// defaults of the command line, for tests.
+#[cfg(test)]
+pub fn test_config(opt_dir: &str, source_path: &str, output_path: &str) -> Config {
+    Config {
+        editor: "vim".to_string(),
+        viewer: "vim".to_string(),
+        runner: "python3".to_string(),
+        opt_dir: opt_dir.to_string(),
+        source_path: source_path.to_string(),
+        output_path: output_path.to_string(),
+        output_policy: OutputPolicy::Refuse,
+        backup_suffix: ".bak".to_string(),
+        execution_mode: ExecutionMode::Combined,
+        jobs: 1,
+        limits: Limits::default(),
+        sandboxed: false,
+        encoding: SourceEncoding::Detect,
+    }
 }
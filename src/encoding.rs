@@ -0,0 +1,215 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, REPLACEMENT, UTF_16BE, UTF_16LE, UTF_8};
+use std::io;
+use std::str::FromStr;
+use update_file_core::{bytes_to_content, content_to_bytes};
+
+/// How the source file is read, see `--encoding`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceEncoding {
+    /// A byte order mark, else UTF-8 if the file is valid UTF-8, else a guess
+    /// from the content.
+    Detect,
+    Text(&'static Encoding),
+    /// The operations run on the bytes, see `OperationManager::set_binary`.
+    Binary,
+}
+
+impl FromStr for SourceEncoding {
+    type Err = String;
+
+    fn from_str(label: &str) -> Result<SourceEncoding, String> {
+        match label {
+            "auto" => Ok(SourceEncoding::Detect),
+            "binary" => Ok(SourceEncoding::Binary),
+            _ => Encoding::for_label(label.as_bytes())
+                .filter(|&encoding| encoding != REPLACEMENT)
+                .map(SourceEncoding::Text)
+                .ok_or_else(|| format!("unknown encoding {}", label)),
+        }
+    }
+}
+
+/// How a source file was read, to write the output the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Text {
+        encoding: &'static Encoding,
+        /// Whether the file starts with a byte order mark.
+        bom: bool,
+    },
+    Binary,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The content of a source file read as `encoding`, and how to write it back.
+pub fn decode(bytes: &[u8], encoding: SourceEncoding) -> Result<(String, Codec), io::Error> {
+    let bom = Encoding::for_bom(bytes);
+    let encoding = match encoding {
+        SourceEncoding::Binary => return Ok((bytes_to_content(bytes), Codec::Binary)),
+        SourceEncoding::Text(encoding) => encoding,
+        SourceEncoding::Detect => match bom {
+            Some((encoding, _)) => encoding,
+            None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+            None => {
+                let mut detector = EncodingDetector::new();
+                detector.feed(bytes, true);
+                detector.guess(None, true)
+            }
+        },
+    };
+    let (bytes, bom) = match bom {
+        Some((bom_encoding, len)) if bom_encoding == encoding => (&bytes[len..], true),
+        _ => (bytes, false),
+    };
+    let content = encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "The source is not valid {}, set its --encoding, or use --encoding binary",
+                encoding.name()
+            ))
+        })?;
+    Ok((content.into_owned(), Codec::Text { encoding, bom }))
+}
+
+impl Codec {
+    /// `content` as bytes to write.
+    pub fn encode(&self, content: &str) -> Result<Vec<u8>, io::Error> {
+        let (encoding, bom) = match *self {
+            Codec::Text { encoding, bom } => (encoding, bom),
+            Codec::Binary => {
+                return content_to_bytes(content).map_err(|e| invalid_data(e.to_string()))
+            }
+        };
+        let mut bytes = Vec::new();
+        if encoding == UTF_16LE || encoding == UTF_16BE {
+            // encoding_rs only decodes UTF-16
+            let big_endian = encoding == UTF_16BE;
+            for unit in std::iter::once(0xfeff)
+                .filter(|_| bom)
+                .chain(content.encode_utf16())
+            {
+                if big_endian {
+                    bytes.extend(unit.to_be_bytes());
+                } else {
+                    bytes.extend(unit.to_le_bytes());
+                }
+            }
+            return Ok(bytes);
+        }
+        if bom {
+            bytes.extend(b"\xef\xbb\xbf");
+        }
+        let (encoded, _, unmappable) = encoding.encode(content);
+        if unmappable {
+            return Err(invalid_data(format!(
+                "The output has characters that {} cannot encode",
+                encoding.name()
+            )));
+        }
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    fn round_trip(bytes: &[u8], encoding: SourceEncoding) -> (String, Codec) {
+        let (content, codec) = decode(bytes, encoding).unwrap();
+        assert_eq!(codec.encode(&content).unwrap(), bytes);
+        (content, codec)
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!("auto".parse(), Ok(SourceEncoding::Detect));
+        assert_eq!("binary".parse(), Ok(SourceEncoding::Binary));
+        assert_eq!("latin1".parse(), Ok(SourceEncoding::Text(WINDOWS_1252)));
+        assert_eq!("UTF-16LE".parse(), Ok(SourceEncoding::Text(UTF_16LE)));
+        assert_eq!(
+            "ebcdic".parse::<SourceEncoding>(),
+            Err("unknown encoding ebcdic".to_string())
+        );
+        assert!("iso-2022-kr".parse::<SourceEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        let (content, codec) = round_trip("héllo\n".as_bytes(), SourceEncoding::Detect);
+        assert_eq!(content, "héllo\n");
+        assert_eq!(
+            codec,
+            Codec::Text {
+                encoding: UTF_8,
+                bom: false
+            }
+        );
+
+        let (content, codec) = round_trip(b"\xef\xbb\xbfversion = 1\n", SourceEncoding::Detect);
+        assert_eq!(content, "version = 1\n");
+        assert_eq!(
+            codec,
+            Codec::Text {
+                encoding: UTF_8,
+                bom: true
+            }
+        );
+
+        let (content, codec) = round_trip(b"\xff\xfeh\x00\xe9\x00\n\x00", SourceEncoding::Detect);
+        assert_eq!(content, "hé\n");
+        assert_eq!(
+            codec,
+            Codec::Text {
+                encoding: UTF_16LE,
+                bom: true
+            }
+        );
+
+        let legacy = b"Le caf\xe9 est tr\xe8s chaud, d\xe9j\xe0 pr\xeat.\n";
+        let (content, codec) = round_trip(legacy, SourceEncoding::Detect);
+        assert_eq!(content, "Le café est très chaud, déjà prêt.\n");
+        assert_eq!(
+            codec,
+            Codec::Text {
+                encoding: WINDOWS_1252,
+                bom: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_explicit_encoding() {
+        let (content, _) = round_trip(b"\x93\xfa\x96\x7b\n", SourceEncoding::Text(SHIFT_JIS));
+        assert_eq!(content, "日本\n");
+        let (content, _) = round_trip(b"\x00h\x00\xe9", SourceEncoding::Text(UTF_16BE));
+        assert_eq!(content, "hé");
+
+        let err = decode(b"caf\xe9", SourceEncoding::Text(UTF_8)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let codec = Codec::Text {
+            encoding: WINDOWS_1252,
+            bom: false,
+        };
+        let err = codec.encode("日本").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The output has characters that windows-1252 cannot encode"
+        );
+    }
+
+    #[test]
+    fn test_binary_encoding() {
+        let bytes = b"\x89PNG\r\n\x1a\n\x00\xff";
+        let (content, codec) = round_trip(bytes, SourceEncoding::Binary);
+        assert_eq!(codec, Codec::Binary);
+        assert_eq!(content.chars().count(), bytes.len());
+        assert!(codec.encode("\u{1f600}").is_err());
+    }
+}
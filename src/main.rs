@@ -1,19 +1,31 @@
+mod batch;
 mod config;
-mod core;
-mod python;
+mod encoding;
 mod utils;
 
-use crate::config::Config;
-use clap::{ArgGroup, Parser};
-use core::OperationManager;
+use crate::batch::run_batch;
+use crate::config::{Config, OutputPolicy};
+use crate::encoding::{decode, Codec, SourceEncoding};
+use clap::{ArgGroup, Parser, ValueEnum};
+use update_file_core::{check_output, Error, ExecutionMode, Limits, OperationManager};
 
-use std::fs;
-use utils::{open_editor, open_viewer};
+use serde_json::json;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use utils::{
+    backup_file, open_editor, open_viewer, read_source, unified_diff, write_file_atomic, STDIO_PATH,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum TraceFormat {
+    Text,
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, arg_required_else_help = true)]
-#[command(group(ArgGroup::new("action").required(true).args(&["edit", "view", "delete", "add", "run", "preview"])))]
-#[command(group(ArgGroup::new("operation").args(&["edit", "view"]).conflicts_with_all(&["delete", "add", "run", "preview"])))]
+#[command(group(ArgGroup::new("action").required(true).args(&["edit", "view", "delete", "add", "enable", "disable", "move_to", "swap", "run", "preview", "trace", "worker", "batch"])))]
+#[command(group(ArgGroup::new("operation").args(&["edit", "view"]).conflicts_with_all(&["delete", "add", "enable", "disable", "move_to", "swap", "run", "preview", "trace", "worker", "batch"])))]
 struct Args {
     #[arg(short = 'E', long, default_value = "vim")]
     pub editor: String,
@@ -21,63 +33,219 @@ struct Args {
     pub viewer: String,
     #[arg(long, default_value = "python3")]
     pub runner: String,
+    /// Run every operation in its own interpreter instead of one shared script
+    #[arg(long)]
+    pub isolated: bool,
+    /// Stop an operation running longer than this many seconds (not with --worker)
+    #[arg(long, value_name = "SECONDS", conflicts_with = "worker")]
+    pub timeout: Option<f64>,
+    /// Stop an operation using more CPU time than this many seconds (not with
+    /// --worker)
+    #[arg(long, value_name = "SECONDS", conflicts_with = "worker")]
+    pub cpu_limit: Option<f64>,
+    /// Stop an operation using more memory than this many MiB (not with --worker)
+    #[arg(long, value_name = "MB", conflicts_with = "worker")]
+    pub memory_limit: Option<u64>,
+    /// Run the operations without network, environment, or write access
+    /// outside a scratch dir (Linux only, not with --worker)
+    #[arg(long, conflicts_with = "worker")]
+    pub sandbox: bool,
+    /// Encoding of the source, e.g. `latin1` or `utf-16le`, detected by
+    /// default; the output is written in the same one. `binary` runs the
+    /// operations on the bytes
+    #[arg(long, value_name = "ENCODING", default_value = "auto")]
+    pub encoding: SourceEncoding,
 
     #[arg(long, required = true)]
     pub opt: String,
-    #[arg(long, required = true)]
-    pub source: String,
-    #[arg(long, required = true)]
-    pub output: String,
+    #[arg(long, required_unless_present = "worker")]
+    pub source: Option<String>,
+    #[arg(long, required_unless_present_any = ["worker", "in_place"])]
+    pub output: Option<String>,
+    /// Write the output over the source file
+    #[arg(short, long, conflicts_with_all = ["output", "batch", "worker"])]
+    pub in_place: bool,
 
     #[arg(short, long, group = "operation")]
     pub edit: bool,
     #[arg(short, long, group = "operation")]
     pub view: bool,
 
-    #[arg(short, long, required_if_eq_any([("edit", "true"), ("view", "true"), ("delete", "true"), ("add", "true")]))]
+    #[arg(short, long, required_if_eq_any([("edit", "true"), ("view", "true"), ("delete", "true"), ("add", "true"), ("enable", "true"), ("disable", "true")]))]
     pub step: Option<usize>,
     #[arg(short, long, group = "action")]
     pub delete: bool,
     #[arg(short, long, group = "action")]
     pub add: bool,
+    /// Enable a disabled operation again
+    #[arg(long, group = "action")]
+    pub enable: bool,
+    /// Skip an operation when running, without deleting it
+    #[arg(long, group = "action")]
+    pub disable: bool,
+    /// Move the operation of --step to this place
+    #[arg(long = "move", value_name = "TO", group = "action", requires = "step")]
+    pub move_to: Option<usize>,
+    /// Swap the operation of --step with this one
+    #[arg(long, value_name = "OTHER", group = "action", requires = "step")]
+    pub swap: Option<usize>,
 
     #[arg(long, group = "action")]
     pub run: bool,
     #[arg(long, group = "action")]
     pub preview: bool,
+    /// Run the operations step by step and report what each one changed
+    #[arg(long, group = "action", value_enum, num_args = 0..=1, default_missing_value = "text")]
+    pub trace: Option<TraceFormat>,
+    /// Keep the interpreter running and answer line-delimited `OperationData`
+    /// JSON from stdin, one `{"output": ...}` or `{"error": ...}` line each
+    #[arg(long, group = "action")]
+    pub worker: bool,
+    /// Run on every file of a --source directory or glob, writing the outputs to
+    /// the same relative paths under the --output directory
+    #[arg(long, group = "action")]
+    pub batch: bool,
+    /// Number of files --batch runs at the same time
+    // only with --batch: `requires = "batch"` would let any other action
+    // through, as clap takes a requirement conflicting with a given argument
+    // as met
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["edit", "view", "delete", "add", "enable", "disable", "move_to", "swap", "run", "preview", "trace", "worker"],
+    )]
+    pub jobs: usize,
+
+    /// Overwrite the output file if it already exists
+    #[arg(long, conflicts_with = "backup")]
+    pub force: bool,
+    /// Keep a copy of an existing output file as `<output>.bak` before overwriting it
+    #[arg(long)]
+    pub backup: bool,
+    /// Keep the backup as `<output><SUFFIX>` instead, implies --backup
+    #[arg(long, value_name = "SUFFIX", conflicts_with = "force")]
+    pub backup_suffix: Option<String>,
 }
 
 fn check_path_exist(paths: Vec<&str>) -> Vec<&str> {
     let mut non_existent_paths = Vec::new();
     for path in paths {
-        if !std::path::Path::new(path).exists() {
+        if path != STDIO_PATH && !std::path::Path::new(path).exists() {
             non_existent_paths.push(path);
         }
     }
     non_existent_paths
 }
 
+/// Read the source as content, and how to encode the output.
+fn read_content(config: &Config) -> Result<(String, Codec), io::Error> {
+    decode(&read_source(&config.source_path)?, config.encoding)
+}
+
+fn write_output(config: &Config, content: &[u8]) -> Result<(), io::Error> {
+    if config.output_path == STDIO_PATH {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(content)?;
+        return stdout.flush();
+    }
+    if Path::new(&config.output_path).exists() {
+        match config.output_policy {
+            OutputPolicy::Refuse => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "Output file {} already exists, use --force or --backup",
+                        config.output_path
+                    ),
+                ));
+            }
+            OutputPolicy::Backup => {
+                let backup_path = backup_file(&config.output_path, &config.backup_suffix)?;
+                eprintln!("Backup output file to {}", backup_path);
+            }
+            OutputPolicy::Overwrite => {}
+        }
+    }
+    write_file_atomic(&config.output_path, content)
+}
+
+/// Answer every `OperationData` line of stdin with one line of JSON on stdout.
+fn serve_worker(
+    opt_manager: &OperationManager,
+    stop_id: usize,
+    python_runner: &str,
+) -> Result<(), Error> {
+    let mut worker = opt_manager.start_worker(stop_id, python_runner)?;
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match worker.run_json(&line) {
+            Ok(output) => json!({ "output": output }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        writeln!(stdout, "{}", reply)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
 fn main() {
     let args = Args::parse();
     run(args);
 }
 
 fn run(args: Args) {
+    let output_policy = if args.force {
+        OutputPolicy::Overwrite
+    } else if args.backup || args.backup_suffix.is_some() {
+        OutputPolicy::Backup
+    } else if args.in_place {
+        OutputPolicy::Overwrite
+    } else {
+        OutputPolicy::Refuse
+    };
+    let source_path = args.source.unwrap_or_default();
+    let output_path = if args.in_place {
+        source_path.clone()
+    } else {
+        args.output.unwrap_or_default()
+    };
     let config = Config {
         editor: args.editor,
         viewer: args.viewer,
         runner: args.runner,
         opt_dir: args.opt,
-        source_path: args.source,
-        output_path: args.output,
+        source_path,
+        output_path,
+        output_policy,
+        backup_suffix: args.backup_suffix.unwrap_or_else(|| ".bak".to_string()),
+        execution_mode: if args.isolated {
+            ExecutionMode::Isolated
+        } else {
+            ExecutionMode::Combined
+        },
+        jobs: args.jobs,
+        limits: Limits {
+            timeout: args.timeout,
+            cpu_time: args.cpu_limit,
+            memory_mb: args.memory_limit,
+        },
+        sandboxed: args.sandbox,
+        encoding: args.encoding,
     };
 
     // Check paths exist
-    let non_existent_paths = check_path_exist(vec![
-        &config.source_path,
-        &config.output_path,
-        &config.opt_dir,
-    ]);
+    // a batch source may be a glob, files are looked up by the batch itself
+    let non_existent_paths = if args.worker || args.batch {
+        check_path_exist(vec![&config.opt_dir])
+    } else {
+        check_path_exist(vec![&config.source_path, &config.opt_dir])
+    };
     if !non_existent_paths.is_empty() {
         eprintln!(
             "Error: The following paths do not exist: {:?}",
@@ -87,17 +255,26 @@ fn run(args: Args) {
     }
 
     let mut opt_manager = OperationManager::new(&config.opt_dir);
+    opt_manager.set_execution_mode(config.execution_mode);
+    opt_manager.set_limits(config.limits);
+    opt_manager.set_sandboxed(config.sandboxed);
+    opt_manager.set_binary(config.encoding == SourceEncoding::Binary);
+    if let Err(e) = opt_manager.recover_renames() {
+        eprintln!("Error: Recovering renumbered operations failed: {}", e);
+        std::process::exit(1);
+    }
     if args.edit || args.view {
         let opt = if args.add {
-            if let Some(id) = args.step {
-                if let Some(opt) = opt_manager.insert_operation(id) {
-                    opt
-                } else {
-                    eprintln!("Error: Operation with id {} already exists", id);
+            let added = match args.step {
+                Some(id) => opt_manager.insert_operation(id),
+                None => opt_manager.add_operation(),
+            };
+            match added {
+                Ok(opt) => opt,
+                Err(e) => {
+                    eprintln!("Error: Adding operation failed: {}", e);
                     std::process::exit(1);
                 }
-            } else {
-                opt_manager.add_operation()
             }
         } else if let Some(id) = args.step {
             if let Some(opt) = opt_manager.get_operation(id) {
@@ -111,7 +288,13 @@ fn run(args: Args) {
             std::process::exit(1);
         };
 
-        let user_content = opt.user_get_content();
+        let user_content = match opt.user_get_content() {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error: Reading operation failed: {}", e);
+                std::process::exit(1);
+            }
+        };
         if args.edit {
             let new_content = open_editor(&config.editor, Some(&user_content));
             if let Ok(content) = new_content {
@@ -131,8 +314,92 @@ fn run(args: Args) {
     }
     if args.delete {
         if let Some(id) = args.step {
-            if !opt_manager.remove_operation(id) {
-                eprintln!("Error: Removing operation with id {} failed", id);
+            if let Err(e) = opt_manager.remove_operation(id) {
+                eprintln!("Error: Removing operation with id {} failed: {}", id, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.enable || args.disable {
+        if let Some(id) = args.step {
+            if !opt_manager.set_operation_enabled(id, args.enable) {
+                eprintln!(
+                    "Error: {} operation with id {} failed",
+                    if args.enable { "Enabling" } else { "Disabling" },
+                    id
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let (Some(to), Some(id)) = (args.move_to, args.step) {
+        if !opt_manager.move_operation(id, to) {
+            eprintln!("Error: Moving operation with id {} to {} failed", id, to);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let (Some(other), Some(id)) = (args.swap, args.step) {
+        if !opt_manager.swap_operations(id, other) {
+            eprintln!(
+                "Error: Swapping operations with id {} and {} failed",
+                id, other
+            );
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(format) = args.trace {
+        let (content, _) = match read_content(&config) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let stop_id = args.step.unwrap_or(usize::MAX);
+        let trace = match opt_manager.trace_operations(stop_id, &content, &config.runner) {
+            Ok(trace) => trace,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if format == TraceFormat::Json {
+            println!("{}", trace.to_json());
+        } else {
+            print!("{}", trace);
+        }
+        if trace.failure.is_some() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.worker {
+        let stop_id = args.step.unwrap_or(usize::MAX);
+        if let Err(e) = serve_worker(&opt_manager, stop_id, &config.runner) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.batch {
+        match run_batch(&config) {
+            Ok(summary) => {
+                print!("{}", summary);
+                if !summary.failed.is_empty() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
         }
@@ -140,52 +407,90 @@ fn run(args: Args) {
     }
 
     if args.run || args.preview {
-        let rsl = fs::read_to_string(&config.source_path);
+        let rsl = read_content(&config);
         if let Err(e) = rsl {
             eprintln!("Error: {}", e);
             std::process::exit(1);
         }
-        let content = rsl.unwrap();
+        let (content, codec) = rsl.unwrap();
         let result = if let Some(id) = args.step {
             opt_manager.run_operations(id, &content, &config.runner)
+        } else if args.preview {
+            // Preview shows whatever the operations produced, even if incomplete
+            opt_manager.run_operations(usize::MAX, &content, &config.runner)
         } else {
             opt_manager.run_all_operations(&content, &config.runner)
         };
 
         if let Err(e) = result {
             eprintln!("Error operation running: {}", e);
+            if let Error::OperationFailed { stderr, .. } = e {
+                eprintln!("{}", stderr);
+            }
             std::process::exit(1);
         }
         let result = result.unwrap();
-        println!("Data Map: {:?}", result.data_map)
+        eprintln!("Data Map: {:?}", result.data_map);
+
+        if args.preview {
+            if let Err(e) = check_output(&result, &content) {
+                eprintln!("Warning: output would not be written: {}", e);
+            }
+            let diff = unified_diff(
+                &content,
+                &result.new_content,
+                &config.source_path,
+                &config.output_path,
+            );
+            if diff.is_empty() {
+                eprintln!("No changes");
+            } else if let Err(e) = open_viewer(&config.viewer, &diff) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        } else {
+            if let Err(e) = check_output(&result, &content) {
+                eprintln!("Error: refuse to write output: {}", e);
+                std::process::exit(1);
+            }
+            let written = codec
+                .encode(&result.new_content)
+                .and_then(|bytes| write_output(&config, &bytes));
+            if let Err(e) = written {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::test_config;
     use clap::error::ErrorKind;
+    use std::fs;
 
     #[test]
     fn test_args_edit_with_step() {
-        let args = Args::parse_from(&[
+        let args = Args::parse_from([
             "test", "--opt", "opt", "--source", "source", "--output", "output", "--edit", "--step",
             "1",
         ]);
         assert_eq!(args.opt, "opt");
-        assert_eq!(args.source, "source");
-        assert_eq!(args.output, "output");
-        assert_eq!(args.edit, true);
+        assert_eq!(args.source.as_deref(), Some("source"));
+        assert_eq!(args.output.as_deref(), Some("output"));
+        assert!(args.edit);
         assert_eq!(args.step, Some(1));
-        assert_eq!(args.delete, false);
-        assert_eq!(args.add, false);
-        assert_eq!(args.run, false);
-        assert_eq!(args.preview, false);
+        assert!(!args.delete);
+        assert!(!args.add);
+        assert!(!args.run);
+        assert!(!args.preview);
     }
 
     #[test]
     fn test_args_edit_without_step() {
-        let result = Args::try_parse_from(&[
+        let result = Args::try_parse_from([
             "test", "--opt", "opt", "--source", "source", "--output", "output", "--edit",
         ]);
         assert!(result.is_err());
@@ -195,7 +500,7 @@ mod tests {
 
     #[test]
     fn test_args_edit_and_view_together() {
-        let result = Args::try_parse_from(&[
+        let result = Args::try_parse_from([
             "test", "--opt", "opt", "--source", "source", "--output", "output", "--edit", "--view",
             "--step", "1",
         ]);
@@ -206,7 +511,7 @@ mod tests {
 
     #[test]
     fn test_args_without_action() {
-        let result = Args::try_parse_from(&[
+        let result = Args::try_parse_from([
             "test", "--opt", "opt", "--source", "source", "--output", "output",
         ]);
         assert!(result.is_err());
@@ -214,6 +519,276 @@ mod tests {
         assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
     }
 
+    #[test]
+    fn test_args_disable_without_step() {
+        let result = Args::try_parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--output",
+            "output",
+            "--disable",
+        ]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--enable",
+            "--step", "2",
+        ]);
+        assert!(args.enable);
+        assert_eq!(args.step, Some(2));
+    }
+
+    #[test]
+    fn test_args_move_and_swap() {
+        let result = Args::try_parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--move", "3",
+        ]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--move", "3",
+            "--step", "1",
+        ]);
+        assert_eq!(args.move_to, Some(3));
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--swap", "3",
+            "--step", "1",
+        ]);
+        assert_eq!(args.swap, Some(3));
+        let result = Args::try_parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--swap", "3",
+            "--move", "2", "--step", "1",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_args_force_and_backup_together() {
+        let result = Args::try_parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--run", "--force",
+            "--backup",
+        ]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_args_trace_format() {
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--trace",
+        ]);
+        assert_eq!(args.trace, Some(TraceFormat::Text));
+        let args = Args::parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--output",
+            "output",
+            "--trace=json",
+        ]);
+        assert_eq!(args.trace, Some(TraceFormat::Json));
+    }
+
+    #[test]
+    fn test_args_worker() {
+        let args = Args::parse_from(["test", "--opt", "opt", "--worker"]);
+        assert!(args.worker);
+        assert_eq!(args.source, None);
+        let result = Args::try_parse_from(["test", "--opt", "opt", "--run"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_args_batch() {
+        let args = Args::parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "configs/**/*.toml",
+            "--output",
+            "out",
+            "--batch",
+        ]);
+        assert!(args.batch);
+        assert_eq!(args.jobs, 1);
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--batch",
+            "--jobs", "4",
+        ]);
+        assert_eq!(args.jobs, 4);
+        let result = Args::try_parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--batch",
+            "--edit", "--step", "1",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Args::try_parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--batch",
+            "--jobs", "0",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+        let result = Args::try_parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--run", "--jobs",
+            "4",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_args_in_place() {
+        let args = Args::parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--in-place",
+            "--backup-suffix",
+            ".orig",
+            "--run",
+        ]);
+        assert!(args.in_place);
+        assert_eq!(args.output, None);
+        assert_eq!(args.backup_suffix.as_deref(), Some(".orig"));
+        let result = Args::try_parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--output",
+            "output",
+            "--in-place",
+            "--run",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        let result = Args::try_parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--in-place",
+            "--force",
+            "--backup-suffix",
+            ".orig",
+            "--run",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_args_isolated() {
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--run",
+        ]);
+        assert!(!args.isolated);
+        let args = Args::parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--output",
+            "output",
+            "--run",
+            "--isolated",
+        ]);
+        assert!(args.isolated);
+    }
+
+    #[test]
+    fn test_args_limits() {
+        let args = Args::parse_from([
+            "test", "--opt", "opt", "--source", "source", "--output", "output", "--run",
+        ]);
+        assert_eq!(args.timeout, None);
+        assert!(!args.sandbox);
+        let args = Args::parse_from([
+            "test",
+            "--opt",
+            "opt",
+            "--source",
+            "source",
+            "--output",
+            "output",
+            "--run",
+            "--timeout",
+            "2.5",
+            "--cpu-limit",
+            "1",
+            "--memory-limit",
+            "512",
+            "--sandbox",
+        ]);
+        assert_eq!(args.timeout, Some(2.5));
+        assert_eq!(args.cpu_limit, Some(1.0));
+        assert_eq!(args.memory_limit, Some(512));
+        assert!(args.sandbox);
+
+        // a worker is not held to limits
+        for limit in ["--timeout", "--cpu-limit", "--memory-limit"] {
+            let result = Args::try_parse_from(["test", "--opt", "opt", "--worker", limit, "1"]);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        }
+        // nor does it isolate its runs from each other
+        let result = Args::try_parse_from(["test", "--opt", "opt", "--worker", "--sandbox"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_check_path_exist() {
+        assert_eq!(
+            check_path_exist(vec![STDIO_PATH, "src", "no-such-path"]),
+            ["no-such-path"]
+        );
+    }
+
+    #[test]
+    fn test_write_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output_path = temp_dir.path().join("output.txt");
+        let mut config = test_config("opt", "source", output_path.to_str().unwrap());
+
+        // output file does not exist
+        write_output(&config, b"Hello").unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "Hello");
+
+        // output file exists
+        let err = write_output(&config, b"World").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "Hello");
+
+        config.output_policy = OutputPolicy::Backup;
+        write_output(&config, b"World").unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "World");
+        let backup_path = format!("{}.bak", config.output_path);
+        assert_eq!(fs::read_to_string(backup_path).unwrap(), "Hello");
+
+        config.output_policy = OutputPolicy::Overwrite;
+        write_output(&config, b"!").unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "!");
+
+        config.output_policy = OutputPolicy::Backup;
+        config.backup_suffix = ".orig".to_string();
+        write_output(&config, b"?").unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "?");
+        let backup_path = format!("{}.orig", config.output_path);
+        assert_eq!(fs::read_to_string(backup_path).unwrap(), "!");
+    }
+
     #[test]
     fn test_run() {}
 }
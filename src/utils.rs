@@ -1,35 +1,10 @@
+use similar::TextDiff;
+use std::fs;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::process::Command;
 use tempfile::NamedTempFile;
 
-pub fn get_content<'a>(
-    content: &'a str,
-    start: &str,
-    end: Option<&str>,
-) -> Result<&'a str, String> {
-    if !content.contains(start) {
-        return Err(format!("Cannot find the start pattern: {}", start));
-    }
-
-    let start_index = content.find(start).unwrap() + start.len();
-
-    if let Some(end) = end {
-        if !content[start_index..].contains(end) {
-            return Err(format!("Cannot find the end pattern: {}", end));
-        }
-        let end_index = content[start_index..].find(end).unwrap();
-        if end_index == 0 {
-            return Err("The resulting content is empty, which may be caused by the end pattern immediately following the start pattern. Please check your patterns for accuracy.".to_string());
-        }
-        Ok(&content[start_index..start_index + end_index])
-    } else {
-        if start_index >= content.len() {
-            return Err("The resulting content is empty because the start pattern is at the end of the content string. Please adjust your start pattern or content.".to_string());
-        }
-        Ok(&content[start_index..])
-    }
-}
-
 pub fn open_editor(editor: &str, init_content: Option<&str>) -> Result<String, io::Error> {
     let mut file = NamedTempFile::new()?;
     if let Some(init_content) = init_content {
@@ -40,19 +15,16 @@ pub fn open_editor(editor: &str, init_content: Option<&str>) -> Result<String, i
     let editor_args = editor.split_whitespace().collect::<Vec<&str>>();
     let editor = editor_args[0];
     let editor_args = &editor_args[1..];
-    println!("Opening editor: {}", editor);
-    println!("Editor args: {:?}", editor_args);
+    eprintln!("Opening editor: {}", editor);
+    eprintln!("Editor args: {:?}", editor_args);
     let status = Command::new(editor).args(editor_args).arg(path).status()?;
     if !status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Editor {} ({}) exited with non-zero status: {}",
-                editor,
-                editor_args.join(" "),
-                status
-            ),
-        ));
+        return Err(io::Error::other(format!(
+            "Editor {} ({}) exited with non-zero status: {}",
+            editor,
+            editor_args.join(" "),
+            status
+        )));
     }
     let mut content = String::new();
     file.read_to_string(&mut content)?;
@@ -68,44 +40,95 @@ pub fn open_viewer(viewer: &str, content: &str) -> Result<(), io::Error> {
     let viewer_args = &viewer_args[1..];
     let status = Command::new(viewer).args(viewer_args).arg(path).status()?;
     if !status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Viewer {} ({}) exited with non-zero status: {}",
-                viewer,
-                viewer_args.join(" "),
-                status
-            ),
-        ));
+        return Err(io::Error::other(format!(
+            "Viewer {} ({}) exited with non-zero status: {}",
+            viewer,
+            viewer_args.join(" "),
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// Source or output path that stands for stdin or stdout.
+pub const STDIO_PATH: &str = "-";
+
+/// Read the whole source file, or stdin for [`STDIO_PATH`].
+pub fn read_source(path: &str) -> Result<Vec<u8>, io::Error> {
+    if path == STDIO_PATH {
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content)?;
+        Ok(content)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Render a unified diff between `old` and `new`, labelled with the given names.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Give `file` the owner and group of `metadata`, as far as we are allowed to.
+/// Like `sed -i`, fall back to only the group, and keep our own ownership if
+/// that fails too.
+#[cfg(unix)]
+fn copy_ownership(file: &fs::File, metadata: &fs::Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    if fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+        let _ = fchown(file, None, Some(metadata.gid()));
+    }
+}
+
+/// Write content to a temporary file next to `path` and rename it over `path`,
+/// so a failure never leaves a half-written file behind. An existing file keeps
+/// its permissions and ownership.
+pub fn write_file_atomic(path: &str, content: &[u8]) -> Result<(), io::Error> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(content)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        #[cfg(unix)]
+        copy_ownership(file.as_file(), &metadata);
+        // after the owner, changing it may clear the setuid and setgid bits
+        file.as_file().set_permissions(metadata.permissions())?;
     }
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|e| e.error)?;
     Ok(())
 }
 
+/// Copy an existing file to `<path><suffix>` and return the backup path.
+pub fn backup_file(path: &str, suffix: &str) -> Result<String, io::Error> {
+    let backup_path = format!("{}{}", path, suffix);
+    fs::copy(path, &backup_path)?;
+    Ok(backup_path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_get_content() {
-        let content = "Hello, world!";
-        assert_eq!(get_content(content, "Hello", None).unwrap(), ", world!");
-        assert_eq!(get_content(content, "Hello", Some("!")).unwrap(), ", world");
-        assert!(get_content(content, "Hello", Some(",")).is_err());
-    }
-
     #[test]
     fn test_open_editor() {
         let editor = "./src/tests/editor.sh";
         let result = open_editor(editor, None);
-        if result.is_err() {
-            eprintln!("Error: {}", result.as_ref().unwrap_err());
+        if let Err(e) = &result {
+            eprintln!("Error: {}", e);
         }
         assert_eq!(result.unwrap(), "");
         let content = "Hello, world!";
         let editor = "./src/tests/editor.sh";
         let result = open_editor(editor, Some(content));
-        if result.is_err() {
-            eprintln!("Error: {}", result.as_ref().unwrap_err());
+        if let Err(e) = &result {
+            eprintln!("Error: {}", e);
         }
         assert_eq!(result.unwrap(), content);
     }
@@ -116,9 +139,65 @@ mod tests {
         let editor = "./src/tests/editor.sh";
         let edit_addition = "test";
         let result = open_editor(&format!("{} {}", editor, edit_addition), Some(content));
-        if result.is_err() {
-            eprintln!("Error: {}", result.as_ref().unwrap_err());
+        if let Err(e) = &result {
+            eprintln!("Error: {}", e);
         }
         assert_eq!(result.unwrap(), format!("{}{}", content, edit_addition));
     }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("Hello\nWorld\n", "Hello\nworld\n", "a", "b");
+        assert_eq!(
+            diff,
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n Hello\n-World\n+world\n"
+        );
+        assert_eq!(unified_diff("Hello\n", "Hello\n", "a", "b"), "");
+    }
+
+    #[test]
+    fn test_write_file_atomic() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("output.txt");
+        let path = path.to_str().unwrap();
+        write_file_atomic(path, b"Hello").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "Hello");
+        write_file_atomic(path, b"World").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "World");
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_atomic_keep_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("script.sh");
+        fs::write(&path, "Hello").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o754)).unwrap();
+        let before = fs::metadata(&path).unwrap();
+
+        write_file_atomic(path.to_str().unwrap(), b"World").unwrap();
+        let after = fs::metadata(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "World");
+        assert_eq!(after.permissions().mode() & 0o7777, 0o754);
+        assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+        // it is a new file, not the old one written over
+        assert_ne!(after.ino(), before.ino());
+    }
+
+    #[test]
+    fn test_backup_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("output.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "Hello").unwrap();
+        let backup_path = backup_file(path, ".bak").unwrap();
+        assert_eq!(backup_path, format!("{}.bak", path));
+        assert_eq!(fs::read_to_string(backup_path).unwrap(), "Hello");
+        let backup_path = backup_file(path, "~").unwrap();
+        assert_eq!(backup_path, format!("{}~", path));
+    }
 }
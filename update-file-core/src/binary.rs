@@ -0,0 +1,61 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::error::Error;
+
+/// Content standing for `bytes`, one character per byte: byte `b` is the
+/// character U+00`b`. This is how content is held in byte mode, see
+/// [`crate::OperationManager::set_binary`].
+pub fn bytes_to_content(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+/// The bytes `content` stands for, the reverse of [`bytes_to_content`].
+pub fn content_to_bytes(content: &str) -> Result<Vec<u8>, Error> {
+    content
+        .chars()
+        .map(|c| u8::try_from(c).map_err(|_| Error::NotBytes(c)))
+        .collect()
+}
+
+/// The bytes `content` stands for, as base64 for the protocol.
+pub(crate) fn encode_base64(content: &str) -> Result<String, Error> {
+    Ok(STANDARD.encode(content_to_bytes(content)?))
+}
+
+/// Content from base64 of the protocol.
+pub(crate) fn decode_base64(encoded: &str) -> Result<String, Error> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(<serde_json::Error as serde::de::Error>::custom)?;
+    Ok(bytes_to_content(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_to_content() {
+        let bytes = [b'a', 0, 0x80, 0xe9, 0xff];
+        let content = bytes_to_content(&bytes);
+        assert_eq!(content.chars().count(), bytes.len());
+        assert_eq!(content_to_bytes(&content).unwrap(), bytes);
+        assert_eq!(
+            decode_base64(&encode_base64(&content).unwrap()).unwrap(),
+            content
+        );
+
+        match content_to_bytes("a\u{1f600}") {
+            Err(e @ Error::NotBytes('\u{1f600}')) => assert_eq!(
+                e.to_string(),
+                "Content in byte mode has the character '\u{1f600}', which is not a byte"
+            ),
+            r => panic!("Expected NotBytes, got {:?}", r),
+        }
+        assert!(matches!(
+            decode_base64("no base64"),
+            Err(Error::ProtocolDecode(_))
+        ));
+    }
+}
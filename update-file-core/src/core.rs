@@ -0,0 +1,1892 @@
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{from_str, json, to_string};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::binary::{decode_base64, encode_base64};
+use crate::error::Error;
+use crate::limits::Limits;
+use crate::manifest::{Manifest, ManifestStep};
+use crate::native::{NativeOperation, NATIVE_EXTENSION};
+use crate::python::*;
+use crate::renumber::{self, RenamePlan};
+use crate::runtime::{create_script, RunContext, Runtime, Runtimes};
+use crate::sandbox::Sandbox;
+use crate::trace::{Trace, TraceFailure, TraceStep};
+
+/// Input handed to the operations.
+///
+/// `content_index` is a byte offset into `full_content`, while operations
+/// count it in Unicode code points, like Python strings do: it is converted
+/// when serializing the data, and back in the output of the operations.
+#[derive(Debug)]
+pub struct OperationData<'a> {
+    pub data_map: &'a HashMap<String, String>,
+    pub full_content: &'a str,
+    pub content_index: usize,
+    /// The new content built by the operations run before.
+    pub new_content: &'a str,
+}
+
+impl Serialize for OperationData<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut data = serializer.serialize_struct("OperationData", 4)?;
+        data.serialize_field("data_map", self.data_map)?;
+        data.serialize_field("full_content", self.full_content)?;
+        data.serialize_field(
+            "content_index",
+            &char_index(self.full_content, self.content_index),
+        )?;
+        data.serialize_field("new_content", self.new_content)?;
+        data.end()
+    }
+}
+
+/// Number of code points of `content` before the byte offset `byte_index`.
+/// An offset past the end stays past the end by as many code points.
+pub(crate) fn char_index(content: &str, byte_index: usize) -> usize {
+    let chars = content
+        .char_indices()
+        .take_while(|&(i, _)| i < byte_index)
+        .count();
+    chars + byte_index.saturating_sub(content.len())
+}
+
+/// Byte offset of the code point `char_index` of `content`, the reverse of
+/// [`char_index`].
+pub(crate) fn byte_index(content: &str, char_index: usize) -> usize {
+    match content.char_indices().nth(char_index) {
+        Some((i, _)) => i,
+        None => content.len() + char_index - content.chars().count(),
+    }
+}
+
+/// Result of running operations: the new content and how much of the source it consumed.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct OperationOutput {
+    pub data_map: HashMap<String, String>,
+    /// A byte offset, like in [`OperationData`].
+    pub content_index: usize,
+    pub new_content: String,
+    pub error_message: String,
+    #[serde(default)]
+    pub error_step: Option<usize>,
+}
+
+impl OperationOutput {
+    /// Output of running no operation on `data`.
+    pub(crate) fn from_data(data: &OperationData) -> OperationOutput {
+        OperationOutput {
+            data_map: data.data_map.clone(),
+            content_index: data.content_index,
+            new_content: data.new_content.to_string(),
+            error_message: String::new(),
+            error_step: None,
+        }
+    }
+
+    /// Input for the operation running after the one that produced this output.
+    pub fn next_data<'a>(&'a self, full_content: &'a str) -> OperationData<'a> {
+        OperationData {
+            data_map: &self.data_map,
+            full_content,
+            content_index: self.content_index,
+            new_content: &self.new_content,
+        }
+    }
+}
+
+/// How the operations of a run are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// All operations run as one script in a single interpreter, so they share
+    /// their globals.
+    #[default]
+    Combined,
+    /// Every operation runs in its own interpreter, and only gets the
+    /// `OperationOutput` of the previous one as input.
+    Isolated,
+}
+
+/// Suffix of a disabled operation file in an opt dir without manifest.
+const DISABLED_SUFFIX: &str = ".disabled";
+
+/// Extension of Python operation files.
+const PYTHON_EXTENSION: &str = "py";
+
+fn dir_file_name(id: usize, extension: &str, enabled: bool) -> String {
+    if enabled {
+        format!("opt-{}.{}", id, extension)
+    } else {
+        format!("opt-{}.{}{}", id, extension, DISABLED_SUFFIX)
+    }
+}
+
+/// A single step of the pipeline. Without a manifest it is stored as
+/// `opt-<id>.py` in the opt dir (`opt-<id>.py.disabled` while disabled), or
+/// with the extension of another [`Runtime`]; with one its id is the position
+/// in the manifest.
+#[derive(Clone)]
+pub struct Operation {
+    id: usize,
+    opt_dir_path: String,
+    file_name: String,
+    enabled: bool,
+    limits: Limits,
+}
+
+impl Operation {
+    fn new(id: usize, opt_dir_path: &str) -> Operation {
+        Operation {
+            id,
+            opt_dir_path: opt_dir_path.to_string(),
+            file_name: format!("opt-{}.py", id),
+            enabled: true,
+            limits: Limits::default(),
+        }
+    }
+
+    fn from_step(id: usize, opt_dir_path: &str, step: &ManifestStep) -> Operation {
+        Operation {
+            id,
+            opt_dir_path: opt_dir_path.to_string(),
+            file_name: step.file.clone(),
+            enabled: step.enabled,
+            limits: step.limits,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Limits set for this operation in the manifest.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Extension of the operation file, which tells how it runs.
+    pub fn extension(&self) -> &str {
+        let file_name = self
+            .file_name
+            .strip_suffix(DISABLED_SUFFIX)
+            .unwrap_or(&self.file_name);
+        Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.extension() == NATIVE_EXTENSION
+    }
+
+    fn file_path(&self) -> String {
+        format!("{}/{}", self.opt_dir_path, self.file_name)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(self.file_path())
+    }
+
+    /// The code of the operation.
+    pub fn content(&self) -> Result<String, Error> {
+        self.get_opt_content()?
+            .ok_or(Error::OperationNotFound { id: self.id })
+    }
+
+    fn get_opt_content(&self) -> Result<Option<String>, Error> {
+        match fs::read(self.file_path()) {
+            Ok(content) => String::from_utf8(content)
+                .map(Some)
+                .map_err(|_| self.not_text()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether the operation is code that can be edited. Compiled operations
+    /// are not, even when their bytes happen to be valid UTF-8.
+    fn is_text(&self) -> bool {
+        self.extension() != "wasm"
+    }
+
+    fn not_text(&self) -> Error {
+        Error::InvalidOperation {
+            id: self.id,
+            file: self.file_name.clone(),
+            message: "the operation file is not text".to_string(),
+        }
+    }
+
+    /// The operation code wrapped in the editable template. Operations other
+    /// than Python ones are edited as they are, and operations that are not
+    /// text cannot be edited.
+    pub fn user_get_content(&self) -> Result<String, Error> {
+        if !self.is_text() {
+            return Err(self.not_text());
+        }
+        let content = self.get_opt_content()?;
+        if self.extension() != PYTHON_EXTENSION {
+            return Ok(content.unwrap_or_default());
+        }
+        Ok(get_operation_temple_python(content.as_deref()))
+    }
+
+    /// Save the code below the template marker of an edited template. Native
+    /// operations are only saved if they are valid, and operations other than
+    /// Python ones are saved as they are. Operations that are not text are
+    /// never saved.
+    pub fn user_write_content(&self, content: &str) -> bool {
+        if !self.is_text() {
+            return false;
+        }
+        if self.is_native() && NativeOperation::parse(content).is_err() {
+            return false;
+        }
+        if self.extension() != PYTHON_EXTENSION {
+            return fs::write(self.file_path(), content).is_ok();
+        }
+        let content = if let Ok(content) = get_operation_python(content) {
+            content
+        } else {
+            return false;
+        };
+        if content.is_empty() {
+            return false;
+        }
+        fs::write(self.file_path(), content).unwrap();
+        true
+    }
+
+    fn delete_opt_content(&self) -> io::Result<()> {
+        fs::remove_file(self.file_path())
+    }
+}
+
+/// `data` as the JSON the operations read. In byte mode the contents are sent
+/// as base64, and `binary` is set.
+pub(crate) fn encode_data(data: &OperationData, binary: bool) -> Result<String, Error> {
+    if !binary {
+        return Ok(to_string(data)?);
+    }
+    Ok(json!({
+        "data_map": data.data_map,
+        "full_content": encode_base64(data.full_content)?,
+        "content_index": char_index(data.full_content, data.content_index),
+        "new_content": encode_base64(data.new_content)?,
+        "binary": true,
+    })
+    .to_string())
+}
+
+/// Read an `OperationOutput` of operations run on `full_content`, blaming an
+/// error without step on `last_id`.
+pub(crate) fn decode_output(
+    output_str: &str,
+    full_content: &str,
+    binary: bool,
+    last_id: Option<usize>,
+) -> Result<OperationOutput, Error> {
+    let mut output: OperationOutput = from_str(output_str)?;
+    output.content_index = byte_index(full_content, output.content_index);
+    if binary {
+        output.new_content = decode_base64(&output.new_content)?;
+    }
+    if !output.error_message.is_empty() && output.error_step.is_none() {
+        output.error_step = last_id;
+    }
+    Ok(output)
+}
+
+/// Run the operations in order. Each run of consecutive operations of the same
+/// [`Runtime`] is handed to it at once, so e.g. consecutive Python operations
+/// share their globals; every part hands its output to the next one. Stops
+/// after the first part in which an operation reports an error.
+fn run_opts(
+    opts: &[Operation],
+    data: &OperationData,
+    runtimes: &Runtimes,
+    context: &RunContext,
+) -> Result<OperationOutput, Error> {
+    let mut output = OperationOutput::from_data(data);
+    for group in opts.chunk_by(|a, b| a.extension() == b.extension()) {
+        let runtime = runtimes.get(&group[0])?;
+        output = runtime.run(group, &output.next_data(data.full_content), context)?;
+        if !output.error_message.is_empty() {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// Like [`run_opts`], also handing the output after each operation to `step`.
+fn trace_opts(
+    opts: &[Operation],
+    data: &OperationData,
+    runtimes: &Runtimes,
+    context: &RunContext,
+    step: &mut dyn FnMut(&Operation, &OperationOutput),
+) -> Result<OperationOutput, Error> {
+    let mut output = OperationOutput::from_data(data);
+    for group in opts.chunk_by(|a, b| a.extension() == b.extension()) {
+        let runtime = runtimes.get(&group[0])?;
+        output = runtime.run_traced(group, &output.next_data(data.full_content), context, step)?;
+        if !output.error_message.is_empty() {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// Run the operations one by one, handing the output of each to the next.
+/// Stops at the first operation that reports an error.
+fn run_opts_isolated(
+    opts: &[Operation],
+    data: &OperationData,
+    runtimes: &Runtimes,
+    context: &RunContext,
+) -> Result<OperationOutput, Error> {
+    let mut output = OperationOutput::from_data(data);
+    for opt in opts {
+        output = run_opts(
+            std::slice::from_ref(opt),
+            &output.next_data(data.full_content),
+            runtimes,
+            context,
+        )?;
+        if !output.error_message.is_empty() {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// Owned `OperationData`, as read from JSON.
+#[derive(Deserialize)]
+struct OperationInput {
+    data_map: HashMap<String, String>,
+    full_content: String,
+    content_index: usize,
+    #[serde(default)]
+    new_content: String,
+}
+
+/// A part of the operations loaded by an [`OperationWorker`].
+enum WorkerStage {
+    Python {
+        worker: PythonWorker,
+        last_id: Option<usize>,
+    },
+    Runtime {
+        runtime: Arc<dyn Runtime>,
+        opts: Vec<Operation>,
+    },
+}
+
+/// Operations loaded once into a running interpreter, to run them on many
+/// inputs without starting the interpreter again. See
+/// [`OperationManager::start_worker`].
+pub struct OperationWorker {
+    stages: Vec<WorkerStage>,
+    python_runner: String,
+    limits: Limits,
+    sandboxed: bool,
+    binary: bool,
+}
+
+impl OperationWorker {
+    pub fn run(&mut self, data: &OperationData) -> Result<OperationOutput, Error> {
+        let mut output = OperationOutput::from_data(data);
+        for stage in &mut self.stages {
+            let next = output.next_data(data.full_content);
+            output = match stage {
+                WorkerStage::Python { worker, last_id } => {
+                    let output_str = worker.run(&encode_data(&next, self.binary)?)?;
+                    decode_output(&output_str, data.full_content, self.binary, *last_id)?
+                }
+                WorkerStage::Runtime { runtime, opts } => {
+                    let context = RunContext {
+                        python_runner: &self.python_runner,
+                        limits: self.limits,
+                        sandboxed: self.sandboxed,
+                        binary: self.binary,
+                    };
+                    runtime.run(opts, &next, &context)?
+                }
+            };
+            if !output.error_message.is_empty() {
+                break;
+            }
+        }
+        Ok(output)
+    }
+
+    /// Like [`OperationWorker::run`], with the `OperationData` as JSON. The
+    /// `content_index` of both the input and the output is in code points, and
+    /// in byte mode the contents of both are base64.
+    pub fn run_json(&mut self, data: &str) -> Result<OperationOutput, Error> {
+        let mut input: OperationInput = from_str(data)?;
+        if self.binary {
+            input.full_content = decode_base64(&input.full_content)?;
+            input.new_content = decode_base64(&input.new_content)?;
+        }
+        let mut output = self.run(&OperationData {
+            data_map: &input.data_map,
+            full_content: &input.full_content,
+            content_index: byte_index(&input.full_content, input.content_index),
+            new_content: &input.new_content,
+        })?;
+        output.content_index = char_index(&input.full_content, output.content_index);
+        if self.binary {
+            output.new_content = encode_base64(&output.new_content)?;
+        }
+        Ok(output)
+    }
+}
+
+/// Check that the operations reported no error and consumed the whole content.
+pub fn check_output(output: &OperationOutput, full_content: &str) -> Result<(), Error> {
+    if !output.error_message.is_empty() {
+        return Err(Error::OperationReported {
+            id: output.error_step,
+            message: output.error_message.clone(),
+        });
+    }
+    if output.content_index != full_content.len() {
+        return Err(Error::IncompleteConsumption {
+            index: char_index(full_content, output.content_index),
+            len: full_content.chars().count(),
+        });
+    }
+    Ok(())
+}
+
+/// The operations of an opt dir, and the data map carried between runs.
+#[derive(Clone)]
+pub struct OperationManager {
+    opt_dir_path: String,
+    data_map: HashMap<String, String>,
+    execution_mode: ExecutionMode,
+    limits: Limits,
+    sandboxed: bool,
+    binary: bool,
+    runtimes: Runtimes,
+}
+
+impl OperationManager {
+    pub fn new(opt_dir_path: &str) -> OperationManager {
+        OperationManager {
+            opt_dir_path: opt_dir_path.to_string(),
+            data_map: HashMap::new(),
+            execution_mode: ExecutionMode::default(),
+            limits: Limits::default(),
+            sandboxed: false,
+            binary: false,
+            runtimes: Runtimes::default(),
+        }
+    }
+
+    pub fn set_execution_mode(&mut self, execution_mode: ExecutionMode) {
+        self.execution_mode = execution_mode;
+    }
+
+    /// Limits of every operation run as its own process, unless its manifest
+    /// step sets its own.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Run the operations that run as processes without network, with a
+    /// read-only filesystem and a cleared environment. Only supported on
+    /// Linux with unprivileged user namespaces; elsewhere these runs fail.
+    pub fn set_sandboxed(&mut self, sandboxed: bool) {
+        self.sandboxed = sandboxed;
+    }
+
+    /// Run the operations in byte mode, on content made with
+    /// [`crate::bytes_to_content`]. Python operations then get `content` and
+    /// `new_content` as `bytes`, and other operations run as processes get
+    /// them as base64, with `binary` set; `content_index` counts bytes.
+    /// Native and Rhai operations see every byte as one character.
+    pub fn set_binary(&mut self, binary: bool) {
+        self.binary = binary;
+    }
+
+    /// Finish or undo a renumbering of the operations interrupted by a crash,
+    /// so they are read as they were before or after it. Renumbering does
+    /// this itself before it starts; call it once before reading the
+    /// operations. Safe to call from several processes at once.
+    pub fn recover_renames(&self) -> Result<(), Error> {
+        renumber::recover_renames(&self.opt_dir_path)
+    }
+
+    /// Run the operation files with the extension of `runtime` through it,
+    /// instead of the runtime built in for that extension, if any.
+    pub fn register_runtime(&mut self, runtime: Arc<dyn Runtime>) {
+        self.runtimes.register(runtime);
+    }
+
+    /// The operations of the opt dir in running order, disabled ones included.
+    /// The order comes from the manifest if there is one, otherwise from the
+    /// ids in the file names.
+    pub fn get_operations(&self) -> Result<Vec<Operation>, Error> {
+        if let Some(manifest) = Manifest::load(&self.opt_dir_path)? {
+            return Ok(manifest
+                .steps
+                .iter()
+                .enumerate()
+                .map(|(i, step)| Operation::from_step(i + 1, &self.opt_dir_path, step))
+                .collect());
+        }
+        Ok(self
+            .get_file_ids()?
+            .into_iter()
+            .map(|(id, extension, enabled)| Operation {
+                file_name: dir_file_name(id, &extension, enabled),
+                enabled,
+                ..Operation::new(id, &self.opt_dir_path)
+            })
+            .collect())
+    }
+
+    /// Ids of the operation files, their extensions and whether they are enabled.
+    fn get_file_ids(&self) -> Result<Vec<(usize, String, bool)>, Error> {
+        // get directory entries
+        let entries = fs::read_dir(&self.opt_dir_path)?;
+        // get file names
+        let mut ids = Vec::new();
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let file_name = file_name.to_string_lossy();
+            let (file_name, enabled) = match file_name.strip_suffix(DISABLED_SUFFIX) {
+                Some(file_name) => (file_name, false),
+                None => (file_name.as_ref(), true),
+            };
+            let name = match file_name.strip_prefix("opt-") {
+                Some(name) => name,
+                None => continue,
+            };
+            for extension in self.runtimes.extensions() {
+                if let Some(id) = name
+                    .strip_suffix(extension)
+                    .and_then(|id| id.strip_suffix('.'))
+                    .and_then(|id| id.parse::<usize>().ok())
+                {
+                    ids.push((id, extension.to_string(), enabled));
+                }
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Ids of the operations in the opt dir, in running order.
+    pub fn get_ids(&self) -> Result<Vec<usize>, Error> {
+        Ok(self.get_operations()?.iter().map(|opt| opt.id).collect())
+    }
+
+    /// The enabled operations among the first `stop_id` ones.
+    fn get_enabled_operations(&self, stop_id: usize) -> Result<Vec<Operation>, Error> {
+        Ok(self
+            .get_operations()?
+            .into_iter()
+            .take(stop_id)
+            .filter(|opt| opt.enabled)
+            .collect())
+    }
+
+    /// Run the first `stop_id` operations on `full_content`.
+    pub fn run_operations(
+        &mut self,
+        stop_id: usize,
+        full_content: &str,
+        python_runner: &str,
+    ) -> Result<OperationOutput, Error> {
+        let opts = self.get_enabled_operations(stop_id)?;
+        let data = OperationData {
+            data_map: &self.data_map,
+            full_content,
+            content_index: 0,
+            new_content: "",
+        };
+        let context = RunContext {
+            python_runner,
+            limits: self.limits,
+            sandboxed: self.sandboxed,
+            binary: self.binary,
+        };
+        let result = match self.execution_mode {
+            ExecutionMode::Combined => run_opts(&opts, &data, &self.runtimes, &context),
+            ExecutionMode::Isolated => run_opts_isolated(&opts, &data, &self.runtimes, &context),
+        };
+        if let Ok(output) = &result {
+            self.data_map = output.data_map.clone();
+        }
+        result
+    }
+
+    /// Run all operations and check the result with [`check_output`].
+    pub fn run_all_operations(
+        &mut self,
+        full_content: &str,
+        python_runner: &str,
+    ) -> Result<OperationOutput, Error> {
+        let result = self.run_operations(usize::MAX, full_content, python_runner);
+        if let Ok(output) = &result {
+            check_output(output, full_content)?;
+        }
+        result
+    }
+
+    /// Start an interpreter with the first `stop_id` enabled operations loaded,
+    /// to run them on many inputs. Consecutive Python operations always run as
+    /// one script, whatever the execution mode, and the data map of the manager
+    /// is not touched. Operations of other runtimes run as usual, but the
+    /// Python interpreter of the worker cannot be held to limits, so Python
+    /// operations with any are refused. When sandboxed, the interpreter runs
+    /// in one sandbox for all its inputs, so the runs are isolated from the
+    /// system but not from each other: what one run leaves in the scratch dir,
+    /// the next can read.
+    pub fn start_worker(
+        &self,
+        stop_id: usize,
+        python_runner: &str,
+    ) -> Result<OperationWorker, Error> {
+        let opts = self.get_enabled_operations(stop_id)?;
+        let mut stages = Vec::new();
+        for group in opts.chunk_by(|a, b| a.extension() == b.extension()) {
+            let runtime = self.runtimes.get(&group[0])?;
+            if group[0].extension() == PYTHON_EXTENSION {
+                let limited = group
+                    .iter()
+                    .find(|opt| !opt.limits().or(self.limits).is_empty());
+                if let Some(opt) = limited {
+                    return Err(Error::InvalidOperation {
+                        id: opt.id,
+                        file: opt.file_name.clone(),
+                        message: "Python operations cannot have limits in a worker".to_string(),
+                    });
+                }
+            }
+            stages.push(if group[0].extension() == PYTHON_EXTENSION {
+                let (script, last_id) = create_script(group)?;
+                WorkerStage::Python {
+                    worker: PythonWorker::start(
+                        script,
+                        python_runner,
+                        self.sandboxed.then(Sandbox::new).transpose()?,
+                    )?,
+                    last_id,
+                }
+            } else {
+                WorkerStage::Runtime {
+                    runtime: runtime.clone(),
+                    opts: group.to_vec(),
+                }
+            });
+        }
+        Ok(OperationWorker {
+            stages,
+            python_runner: python_runner.to_string(),
+            limits: self.limits,
+            sandboxed: self.sandboxed,
+            binary: self.binary,
+        })
+    }
+
+    /// Run the first `stop_id` operations and record how each step changed
+    /// the output. In combined mode they run together once, like
+    /// [`OperationManager::run_operations`] runs them, and each step is
+    /// recorded as it finishes.
+    pub fn trace_operations(
+        &mut self,
+        stop_id: usize,
+        full_content: &str,
+        python_runner: &str,
+    ) -> Result<Trace, Error> {
+        let opts = self.get_enabled_operations(stop_id)?;
+        let data = OperationData {
+            data_map: &self.data_map,
+            full_content,
+            content_index: 0,
+            new_content: "",
+        };
+        let context = RunContext {
+            python_runner,
+            limits: self.limits,
+            sandboxed: self.sandboxed,
+            binary: self.binary,
+        };
+        let mut trace = Trace::default();
+        let mut prev = OperationOutput::from_data(&data);
+        let mut record = |opt: &Operation, output: &OperationOutput| {
+            trace.steps.push(TraceStep::new(
+                opt.id,
+                &opt.file_name,
+                full_content,
+                &prev,
+                output,
+            ));
+            prev = output.clone();
+        };
+        let result = match self.execution_mode {
+            ExecutionMode::Combined => {
+                trace_opts(&opts, &data, &self.runtimes, &context, &mut record)
+            }
+            ExecutionMode::Isolated => {
+                let mut output = OperationOutput::from_data(&data);
+                let mut result = Ok(());
+                for opt in &opts {
+                    match trace_opts(
+                        std::slice::from_ref(opt),
+                        &output.next_data(full_content),
+                        &self.runtimes,
+                        &context,
+                        &mut record,
+                    ) {
+                        Ok(next) => output = next,
+                        Err(e) => {
+                            result = Err(e);
+                            break;
+                        }
+                    }
+                    if !output.error_message.is_empty() {
+                        break;
+                    }
+                }
+                result.map(|_| output)
+            }
+        };
+        if let Err(e) = result {
+            // the first step not recorded is the one that failed
+            let id = opts.get(trace.steps.len()).map_or(stop_id, |opt| opt.id);
+            trace.failure = Some(TraceFailure {
+                id,
+                message: e.to_string(),
+            });
+        }
+        self.data_map = prev.data_map;
+        Ok(trace)
+    }
+
+    /// Insert a new operation at `id`, shifting the operations from `id` on by
+    /// one.
+    pub fn insert_operation(&mut self, id: usize) -> Result<Operation, Error> {
+        if let Some(mut manifest) = Manifest::load(&self.opt_dir_path)? {
+            let index = id.saturating_sub(1).min(manifest.steps.len());
+            let (name, file) = manifest.new_file_name(&self.opt_dir_path);
+            let step = ManifestStep {
+                name,
+                description: String::new(),
+                enabled: true,
+                file,
+                limits: Limits::default(),
+            };
+            manifest.steps.insert(index, step.clone());
+            manifest.save(&self.opt_dir_path)?;
+            return Ok(Operation::from_step(index + 1, &self.opt_dir_path, &step));
+        }
+        let opts = self.get_operations()?;
+        let index = opts.iter().position(|opt| opt.id == id);
+        if let Some(index) = index {
+            let mut plan = RenamePlan::default();
+            for opt in opts[index..].iter().rev() {
+                plan.push(
+                    &opt.file_name,
+                    &dir_file_name(opt.id + 1, opt.extension(), opt.enabled),
+                );
+            }
+            plan.apply(&self.opt_dir_path)?;
+        }
+        Ok(Operation::new(id, &self.opt_dir_path))
+    }
+
+    /// Add a new operation after the last one.
+    pub fn add_operation(&mut self) -> Result<Operation, Error> {
+        let id = self.get_ids()?.len() + 1;
+        self.insert_operation(id)
+    }
+
+    /// Remove the operation `id` and its file. With a manifest the step is
+    /// dropped from it first, so a failure never leaves a listed step without
+    /// its file; without one the operations after it are renumbered.
+    pub fn remove_operation(&mut self, id: usize) -> Result<(), Error> {
+        if let Some(mut manifest) = Manifest::load(&self.opt_dir_path)? {
+            if id == 0 || id > manifest.steps.len() {
+                return Err(Error::OperationNotFound { id });
+            }
+            let step = manifest.steps.remove(id - 1);
+            manifest.save(&self.opt_dir_path)?;
+            return match Operation::from_step(id, &self.opt_dir_path, &step).delete_opt_content() {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        let opt = self
+            .get_operation(id)
+            .ok_or(Error::OperationNotFound { id })?;
+        opt.delete_opt_content()?;
+        self.resort_operations()
+    }
+
+    pub fn get_operation(&self, id: usize) -> Option<Operation> {
+        self.get_operations()
+            .ok()?
+            .into_iter()
+            .find(|opt| opt.id == id)
+    }
+
+    /// Move the operation `from` to the place of `to`, shifting the ones in
+    /// between by one.
+    pub fn move_operation(&mut self, from: usize, to: usize) -> bool {
+        let ids = match self.get_ids() {
+            Ok(ids) => ids,
+            Err(_) => return false,
+        };
+        let from = ids.iter().position(|&id| id == from);
+        let to = ids.iter().position(|&id| id == to);
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return false,
+        };
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        let index = order.remove(from);
+        order.insert(to, index);
+        self.reorder_operations(&order)
+    }
+
+    pub fn swap_operations(&mut self, a: usize, b: usize) -> bool {
+        let ids = match self.get_ids() {
+            Ok(ids) => ids,
+            Err(_) => return false,
+        };
+        let a = ids.iter().position(|&id| id == a);
+        let b = ids.iter().position(|&id| id == b);
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.swap(a, b);
+        self.reorder_operations(&order)
+    }
+
+    /// Put the operations in a new order: the operation at position `order[i]`
+    /// takes place `i`.
+    fn reorder_operations(&mut self, order: &[usize]) -> bool {
+        let manifest = match Manifest::load(&self.opt_dir_path) {
+            Ok(manifest) => manifest,
+            Err(_) => return false,
+        };
+        if let Some(mut manifest) = manifest {
+            manifest.steps = order.iter().map(|&i| manifest.steps[i].clone()).collect();
+            return manifest.save(&self.opt_dir_path).is_ok();
+        }
+        let opts = match self.get_operations() {
+            Ok(opts) => opts,
+            Err(_) => return false,
+        };
+        let mut plan = RenamePlan::default();
+        for (slot, &i) in order.iter().enumerate() {
+            let opt = &opts[i];
+            plan.push(
+                &opt.file_name,
+                &dir_file_name(opts[slot].id, opt.extension(), opt.enabled),
+            );
+        }
+        plan.apply(&self.opt_dir_path).is_ok()
+    }
+
+    /// Enable or disable an operation without deleting it. Disabled operations
+    /// are skipped when running.
+    pub fn set_operation_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        let manifest = match Manifest::load(&self.opt_dir_path) {
+            Ok(manifest) => manifest,
+            Err(_) => return false,
+        };
+        if let Some(mut manifest) = manifest {
+            if id == 0 || id > manifest.steps.len() {
+                return false;
+            }
+            manifest.steps[id - 1].enabled = enabled;
+            return manifest.save(&self.opt_dir_path).is_ok();
+        }
+        let opt = if let Some(opt) = self.get_operation(id) {
+            opt
+        } else {
+            return false;
+        };
+        if opt.enabled == enabled {
+            return true;
+        }
+        let new_file_path = format!(
+            "{}/{}",
+            self.opt_dir_path,
+            dir_file_name(id, opt.extension(), enabled)
+        );
+        fs::rename(opt.file_path(), new_file_path).is_ok()
+    }
+
+    /// Renumber the operations to close the gaps between their ids. Either
+    /// all files are renamed or none is.
+    fn resort_operations(&mut self) -> Result<(), Error> {
+        let opts = self.get_operations()?;
+        if opts.windows(2).all(|w| w[1].id - w[0].id == 1) {
+            return Ok(());
+        }
+
+        let mut plan = RenamePlan::default();
+        for (i, opt) in opts.iter().enumerate() {
+            plan.push(
+                &opt.file_name,
+                &dir_file_name(i + 1, opt.extension(), opt.enabled),
+            );
+        }
+        plan.apply(&self.opt_dir_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::manifest::MANIFEST_FILE_NAME;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_output() {
+        let mut output = OperationOutput {
+            data_map: HashMap::new(),
+            content_index: 5,
+            new_content: "Hello".to_string(),
+            error_message: String::new(),
+            error_step: None,
+        };
+        assert!(check_output(&output, "Hello").is_ok());
+        assert!(matches!(
+            check_output(&output, "Hello, World!"),
+            Err(Error::IncompleteConsumption { index: 5, len: 13 })
+        ));
+
+        // an error is never blamed on a step that did not set it
+        output.error_message = "failed".to_string();
+        let e = check_output(&output, "Hello").unwrap_err();
+        assert!(matches!(e, Error::OperationReported { id: None, .. }));
+        assert_eq!(e.to_string(), "Error from an operation: failed");
+        output.error_step = Some(2);
+        assert_eq!(
+            check_output(&output, "Hello").unwrap_err().to_string(),
+            "Error from operation 2: failed"
+        );
+    }
+
+    #[test]
+    fn test_write_read_opt_content() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+
+        // create Operation instance
+        let opt = Operation::new(0, opt_dir_path);
+
+        // write operation content
+        let user_content = get_operation_temple_python(None) + "\nprint('Hello, World!')\n";
+        assert!(opt.user_write_content(&user_content));
+        assert_eq!(
+            opt.get_opt_content().unwrap().unwrap(),
+            get_operation_python(&user_content).unwrap()
+        );
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn test_edit_compiled_operation() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        // valid UTF-8, but still not text
+        let module = b"\0asm\x01\0\0\0";
+        let path = temp_dir.path().join("opt-1.wasm");
+        fs::write(&path, module).unwrap();
+
+        let opt = OperationManager::new(opt_dir_path)
+            .get_operation(1)
+            .unwrap();
+        assert!(matches!(
+            opt.user_get_content(),
+            Err(Error::InvalidOperation { id: 1, .. })
+        ));
+        assert!(!opt.user_write_content("changed"));
+        assert_eq!(fs::read(&path).unwrap(), module);
+    }
+
+    #[test]
+    fn test_rename_opt_content() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+
+        // create Operation instance
+        let opt = Operation::new(0, opt_dir_path);
+
+        // write operation content
+        let user_content = &get_operation_temple_python(None);
+        assert!(opt.user_write_content(user_content));
+        let file_content = get_operation_python(user_content).unwrap();
+
+        // rename operation content
+        let mut plan = RenamePlan::default();
+        plan.push(
+            opt.file_name(),
+            &dir_file_name(1, opt.extension(), opt.is_enabled()),
+        );
+        assert!(plan.apply(opt_dir_path).is_ok());
+        let opt = Operation::new(1, opt_dir_path);
+        assert_eq!(opt.get_opt_content().unwrap().unwrap(), file_content);
+        let file_path = format!("{}/opt-1.py", opt_dir_path);
+        let file_old_path = format!("{}/opt-0.py", opt_dir_path);
+        assert!(Path::new(&file_path).exists());
+        assert!(!Path::new(&file_old_path).exists());
+        assert_eq!(fs::read_to_string(file_path).unwrap(), file_content);
+    }
+
+    #[test]
+    fn test_manager_insert_operation() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+
+        // create OperationManager instance
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        let user_content = &get_operation_temple_python(None);
+        // create Operation instance
+        let opt1 = manager.insert_operation(1).unwrap();
+        assert!(opt1.user_write_content(user_content));
+        let opt2 = manager.insert_operation(2).unwrap();
+        assert!(opt2.user_write_content(user_content));
+        let opt3 = manager.insert_operation(3).unwrap();
+        assert!(opt3.user_write_content(user_content));
+
+        // check operation count and operation ID
+        assert_eq!(manager.get_ids().unwrap().len(), 3);
+        let ids = manager.get_ids().unwrap();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // insert operation
+        let opt4 = manager.insert_operation(2).unwrap();
+        assert_eq!(opt4.id, 2);
+
+        // check operation count and operation ID
+        let ids = manager.get_ids().unwrap();
+        assert_eq!(ids, vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_manager_insert_operation_renumber() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        write_numbered_operations(opt_dir_path, &[1, 2, 3]);
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        assert!(manager.set_operation_enabled(3, false));
+        let opt = manager.insert_operation(2).unwrap();
+        assert_eq!(opt.id(), 2);
+        assert!(!temp_dir.path().join("opt-2.py").exists());
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 3, 4]);
+        assert_eq!(read_numbered_operations(&manager), vec!["1", "2", "3"]);
+        assert!(temp_dir.path().join("opt-4.py.disabled").exists());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+
+        // closing the gap again renumbers everything after it
+        fs::write(temp_dir.path().join("opt-2.py"), "new").unwrap();
+        manager.remove_operation(3).unwrap();
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2, 3]);
+        assert_eq!(read_numbered_operations(&manager), vec!["1", "new", "3"]);
+        assert!(temp_dir.path().join("opt-3.py.disabled").exists());
+    }
+
+    #[test]
+    fn test_manager_remove_operation() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+
+        // create OperationManager instance
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        let user_content = &get_operation_temple_python(None);
+        // create Operation instance
+        let opt1 = manager.insert_operation(1).unwrap();
+        opt1.user_write_content(user_content);
+        let opt2 = manager.insert_operation(2).unwrap();
+        opt2.user_write_content(user_content);
+        let opt3 = manager.insert_operation(3).unwrap();
+        opt3.user_write_content(user_content);
+
+        // check operation count and operation ID
+        let ids = manager.get_ids().unwrap();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // remove operation
+        manager.remove_operation(2).unwrap();
+
+        // check operation count and operation ID
+        let ids = manager.get_ids().unwrap();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_manager_run_operation() {
+        let opt_dir_path = "./src/tests";
+
+        // create OperationManager instance
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        // run operation
+        let full_content = "Hello, World!";
+        let python_runner = "python3";
+        let output = manager
+            .run_operations(1, full_content, python_runner)
+            .unwrap();
+        assert_eq!(output.error_message, "");
+        assert_eq!(output.content_index, 0);
+        let output = manager
+            .run_operations(2, full_content, python_runner)
+            .unwrap();
+        assert_eq!(output.error_message, "");
+        assert_eq!(output.content_index, full_content.len());
+        assert!(!output.new_content.is_empty())
+    }
+
+    #[test]
+    fn test_manager_trace_operations() {
+        let mut manager = OperationManager::new("./src/tests");
+
+        let full_content = "Hello, World!";
+        let trace = manager
+            .trace_operations(2, full_content, "python3")
+            .unwrap();
+        assert!(trace.failure.is_none());
+        assert_eq!(trace.steps.len(), 2);
+
+        let step1 = &trace.steps[0];
+        assert_eq!(step1.id, 1);
+        assert_eq!(step1.content_index, 0);
+        assert_eq!(step1.added_content, "");
+        assert_eq!(step1.data_map_delta.added["start"], full_content);
+
+        let step2 = &trace.steps[1];
+        assert_eq!(step2.id, 2);
+        assert_eq!(step2.content_index, full_content.len());
+        assert_eq!(step2.added_content, "hello, world!\n");
+        assert_eq!(step2.data_map_delta.added["end"], full_content);
+        assert!(step2.data_map_delta.changed.is_empty());
+    }
+
+    #[test]
+    fn test_manager_trace_operations_once() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let log_path = temp_dir.path().join("runs.log");
+        for id in 1..=3 {
+            fs::write(
+                temp_dir.path().join(format!("opt-{}.py", id)),
+                format!(
+                    "open({:?}, 'a').write('{}')\nshared = {}\ndata_map['step'] = str(shared)",
+                    log_path.to_str().unwrap(),
+                    id,
+                    id
+                ),
+            )
+            .unwrap();
+        }
+        fs::write(
+            temp_dir.path().join("opt-4.py"),
+            "new_content += content\ncontent_index = len(content)\nraise ValueError(shared)",
+        )
+        .unwrap();
+
+        // every step runs once, and the steps still share their globals
+        let mut manager = OperationManager::new(opt_dir_path);
+        let trace = manager.trace_operations(4, "Hello", "python3").unwrap();
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), "123");
+        assert_eq!(trace.steps.len(), 3);
+        for (step, id) in trace.steps.iter().zip(1..) {
+            assert_eq!(step.id, id);
+        }
+        assert_eq!(trace.steps[2].data_map_delta.changed["step"].new, "3");
+        let failure = trace.failure.unwrap();
+        assert_eq!(failure.id, 4);
+        assert!(failure.message.contains("ValueError: 3"));
+    }
+
+    #[test]
+    fn test_manager_enable_operation() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::copy("./src/tests/opt-1.py", temp_dir.path().join("opt-1.py")).unwrap();
+        fs::write(temp_dir.path().join("opt-2.py"), "error_message = 'failed'").unwrap();
+        fs::copy("./src/tests/opt-2.py", temp_dir.path().join("opt-3.py")).unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let full_content = "Hello, World!";
+        assert!(manager.run_all_operations(full_content, "python3").is_err());
+
+        assert!(manager.set_operation_enabled(2, false));
+        assert!(temp_dir.path().join("opt-2.py.disabled").exists());
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2, 3]);
+        assert!(!manager.get_operation(2).unwrap().is_enabled());
+        let output = manager.run_all_operations(full_content, "python3").unwrap();
+        assert_eq!(output.new_content, "hello, world!\n");
+
+        // disabled operations stay disabled when renumbering
+        assert!(manager.set_operation_enabled(2, true));
+        assert!(manager.set_operation_enabled(3, false));
+        manager.remove_operation(2).unwrap();
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2]);
+        assert!(temp_dir.path().join("opt-2.py.disabled").exists());
+
+        assert!(manager.set_operation_enabled(2, true));
+        assert!(temp_dir.path().join("opt-2.py").exists());
+        assert!(manager.get_operation(2).unwrap().is_enabled());
+        assert!(manager.run_all_operations(full_content, "python3").is_ok());
+        assert!(!manager.set_operation_enabled(3, true));
+    }
+
+    fn write_numbered_operations(opt_dir_path: &str, ids: &[usize]) {
+        for id in ids {
+            fs::write(format!("{}/opt-{}.py", opt_dir_path, id), id.to_string()).unwrap();
+        }
+    }
+
+    fn read_numbered_operations(manager: &OperationManager) -> Vec<String> {
+        manager
+            .get_operations()
+            .unwrap()
+            .iter()
+            .map(|opt| opt.get_opt_content().unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_manager_move_operation() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        write_numbered_operations(opt_dir_path, &[1, 2, 3, 4]);
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        assert!(manager.set_operation_enabled(2, false));
+        assert!(manager.move_operation(2, 4));
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(read_numbered_operations(&manager), vec!["1", "3", "4", "2"]);
+        assert!(!manager.get_operation(4).unwrap().is_enabled());
+
+        assert!(manager.move_operation(4, 1));
+        assert_eq!(read_numbered_operations(&manager), vec!["2", "1", "3", "4"]);
+        assert!(!manager.get_operation(1).unwrap().is_enabled());
+
+        assert!(!manager.move_operation(1, 5));
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_manager_swap_operations() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        write_numbered_operations(opt_dir_path, &[1, 2, 3]);
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        assert!(manager.swap_operations(1, 3));
+        assert_eq!(read_numbered_operations(&manager), vec!["3", "2", "1"]);
+        assert!(!manager.swap_operations(0, 3));
+
+        // with a manifest only the manifest changes
+        let manifest = Manifest {
+            steps: ["a.py", "b.py"]
+                .iter()
+                .map(|file| ManifestStep {
+                    name: file.to_string(),
+                    description: String::new(),
+                    enabled: true,
+                    file: file.to_string(),
+                    limits: Limits::default(),
+                })
+                .collect(),
+        };
+        manifest.save(opt_dir_path).unwrap();
+        assert!(manager.swap_operations(1, 2));
+        let manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        let files: Vec<&str> = manifest.steps.iter().map(|s| s.file.as_str()).collect();
+        assert_eq!(files, vec!["b.py", "a.py"]);
+    }
+
+    #[test]
+    fn test_manager_manifest_order() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::copy("./src/tests/opt-1.py", temp_dir.path().join("start.py")).unwrap();
+        fs::copy("./src/tests/opt-2.py", temp_dir.path().join("lower.py")).unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "error_message = 'ignored'",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join(MANIFEST_FILE_NAME),
+            r#"
+[[step]]
+name = "start"
+file = "start.py"
+
+[[step]]
+name = "skipped"
+file = "opt-1.py"
+enabled = false
+
+[[step]]
+name = "lower"
+file = "lower.py"
+"#,
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2, 3]);
+        assert_eq!(manager.get_operation(3).unwrap().file_name(), "lower.py");
+        assert!(!manager.get_operation(2).unwrap().is_enabled());
+
+        let full_content = "Hello, World!";
+        let output = manager.run_all_operations(full_content, "python3").unwrap();
+        assert_eq!(output.new_content, "hello, world!\n");
+
+        // a broken manifest is an error, not an empty pipeline
+        fs::write(
+            temp_dir.path().join(MANIFEST_FILE_NAME),
+            "[[step]]\nname = ",
+        )
+        .unwrap();
+        assert!(matches!(manager.get_ids(), Err(Error::ManifestDecode(_))));
+        assert!(matches!(
+            manager.add_operation(),
+            Err(Error::ManifestDecode(_))
+        ));
+        assert!(matches!(
+            manager.run_all_operations(full_content, "python3"),
+            Err(Error::ManifestDecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_manager_add_operation() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let user_content = &get_operation_temple_python(None);
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        for id in 1..=3 {
+            let opt = manager.add_operation().unwrap();
+            assert_eq!(opt.id(), id);
+            assert!(opt.user_write_content(user_content));
+        }
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2, 3]);
+        assert!(temp_dir.path().join("opt-3.py").exists());
+    }
+
+    #[test]
+    fn test_manager_manifest_insert_remove() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let manifest = Manifest {
+            steps: vec![ManifestStep {
+                name: "first".to_string(),
+                description: String::new(),
+                enabled: true,
+                file: "first.py".to_string(),
+                limits: Limits::default(),
+            }],
+        };
+        manifest.save(opt_dir_path).unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let user_content = &get_operation_temple_python(None);
+        let opt = manager.insert_operation(1).unwrap();
+        assert!(opt.user_write_content(user_content));
+        assert_eq!(opt.file_name(), "step-2.py");
+
+        let manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        let files: Vec<&str> = manifest.steps.iter().map(|s| s.file.as_str()).collect();
+        assert_eq!(files, vec!["step-2.py", "first.py"]);
+
+        // an added step comes last
+        let opt = manager.add_operation().unwrap();
+        assert_eq!(opt.id(), 3);
+        let manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        assert_eq!(manifest.steps[2].file, opt.file_name());
+        // its file was never written, the step is still removed
+        manager.remove_operation(3).unwrap();
+        assert_eq!(
+            Manifest::load(opt_dir_path).unwrap().unwrap().steps.len(),
+            2
+        );
+
+        // file names stay the same, only the manifest changes
+        manager.remove_operation(1).unwrap();
+        assert!(!temp_dir.path().join("step-2.py").exists());
+        let manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        assert_eq!(manifest.steps.len(), 1);
+        assert_eq!(manifest.steps[0].file, "first.py");
+        assert!(matches!(
+            manager.remove_operation(2),
+            Err(Error::OperationNotFound { id: 2 })
+        ));
+
+        assert!(manager.set_operation_enabled(1, false));
+        let manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        assert!(!manifest.steps[0].enabled);
+    }
+
+    #[test]
+    fn test_manager_run_isolated() {
+        let mut manager = OperationManager::new("./src/tests");
+        manager.set_execution_mode(ExecutionMode::Isolated);
+
+        // the output of a step is the input of the next
+        let full_content = "Hello, World!";
+        let output = manager.run_all_operations(full_content, "python3").unwrap();
+        assert_eq!(output.new_content, "hello, world!\n");
+        assert_eq!(output.data_map["step"], "1");
+        assert_eq!(output.data_map["end"], full_content);
+
+        let trace = manager
+            .trace_operations(2, full_content, "python3")
+            .unwrap();
+        assert_eq!(trace.steps[1].added_content, "hello, world!\n");
+    }
+
+    #[test]
+    fn test_manager_run_isolated_globals() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("opt-1.py"), "shared = content.upper()").unwrap();
+        fs::write(
+            temp_dir.path().join("opt-2.py"),
+            "new_content += shared\ncontent_index = len(content)",
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let output = manager.run_all_operations("Hello", "python3").unwrap();
+        assert_eq!(output.new_content, "HELLO");
+
+        manager.set_execution_mode(ExecutionMode::Isolated);
+        match manager.run_all_operations("Hello", "python3") {
+            Err(Error::OperationFailed { id, message, .. }) => {
+                assert_eq!(id, 2);
+                assert_eq!(message, "NameError: name 'shared' is not defined");
+            }
+            r => panic!("Expected OperationFailed, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_manager_worker() {
+        let manager = OperationManager::new("./src/tests");
+        let mut worker = manager.start_worker(2, "python3").unwrap();
+        let data_map = HashMap::new();
+        for full_content in ["Hello, World!", "Second file"] {
+            let output = worker
+                .run(&OperationData {
+                    data_map: &data_map,
+                    full_content,
+                    content_index: 0,
+                    new_content: "",
+                })
+                .unwrap();
+            assert_eq!(output.new_content, full_content.to_lowercase() + "\n");
+            assert_eq!(output.data_map["end"], full_content);
+        }
+
+        // a bad input fails alone, the worker keeps running
+        match worker.run_json(r#"{"data_map": {}, "content_index": 0}"#) {
+            Err(Error::ProtocolDecode(e)) => assert!(e.to_string().contains("full_content")),
+            r => panic!("Expected ProtocolDecode, got {:?}", r),
+        }
+        let output = worker
+            .run_json(r#"{"data_map": {}, "full_content": "Hello", "content_index": 0}"#)
+            .unwrap();
+        assert_eq!(output.new_content, "hello\n");
+
+        // the interpreter of a worker cannot be held to limits
+        let mut manager = manager;
+        manager.set_limits(Limits {
+            timeout: Some(1.0),
+            ..Limits::default()
+        });
+        match manager.start_worker(2, "python3") {
+            Err(e @ Error::InvalidOperation { .. }) => assert_eq!(
+                e.to_string(),
+                "Invalid operation 1 (opt-1.py): Python operations cannot have limits in a worker"
+            ),
+            r => panic!("Expected InvalidOperation, got {:?}", r.err()),
+        }
+    }
+
+    #[test]
+    fn test_manager_run_native() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.toml"),
+            "kind = 'set-data'\npattern = 'version = (\\d+)'\nkey = 'version'",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-2.toml"),
+            "kind = 'regex-replace'\npattern = 'version = \\d+'\nreplacement = 'version = 2'",
+        )
+        .unwrap();
+        let mut manager = OperationManager::new(opt_dir_path);
+        assert_eq!(manager.get_ids().unwrap(), vec![1, 2]);
+        assert!(manager.get_operation(2).unwrap().is_native());
+
+        // no Python needed
+        let output = manager
+            .run_all_operations("name = x\nversion = 1\n", "no-such-python")
+            .unwrap();
+        assert_eq!(output.new_content, "name = x\nversion = 2\n");
+        assert_eq!(output.data_map["version"], "1");
+
+        let result = manager.run_all_operations("name = x\n", "no-such-python");
+        match result {
+            Err(Error::OperationReported { id, .. }) => assert_eq!(id, Some(1)),
+            r => panic!("Expected OperationReported, got {:?}", r),
+        }
+
+        fs::write(temp_dir.path().join("opt-2.toml"), "kind = 'unknown'").unwrap();
+        let result = manager.run_all_operations("version = 1", "no-such-python");
+        match result {
+            Err(Error::InvalidOperation { id, file, .. }) => {
+                assert_eq!(id, 2);
+                assert_eq!(file, "opt-2.toml");
+            }
+            r => panic!("Expected InvalidOperation, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_manager_run_native_and_python() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "line = content.split('\\n', 1)[0] + '\\n'\nnew_content += line.upper()\ncontent_index += len(line)",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-2.toml"),
+            "kind = 'replace'\npattern = 'a'\nreplacement = 'b'",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-3.py"),
+            "data_map['length'] = str(len(new_content))",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-4.toml"),
+            "kind = 'insert-lines'\nline = 1\ntext = '# header'\napply_to = 'output'",
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let full_content = "aaa\naaa\n";
+        let expected = "# header\nAAA\nbbb\n";
+        let output = manager.run_all_operations(full_content, "python3").unwrap();
+        assert_eq!(output.new_content, expected);
+        assert_eq!(output.data_map["length"], "8");
+
+        let trace = manager
+            .trace_operations(4, full_content, "python3")
+            .unwrap();
+        assert_eq!(trace.steps[1].added_content, "bbb\n");
+        let mut worker = manager.start_worker(4, "python3").unwrap();
+        for _ in 0..2 {
+            let output = worker
+                .run_json(r#"{"data_map": {}, "full_content": "aaa\naaa\n", "content_index": 0}"#)
+                .unwrap();
+            assert_eq!(output.new_content, expected);
+        }
+
+        // renumbering keeps the extensions
+        assert!(manager.move_operation(4, 1));
+        assert!(temp_dir.path().join("opt-1.toml").exists());
+        assert!(temp_dir.path().join("opt-3.toml").exists());
+        assert!(manager.set_operation_enabled(3, false));
+        assert!(temp_dir.path().join("opt-3.toml.disabled").exists());
+        assert!(!manager.get_operation(3).unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_char_index() {
+        let content = "h\u{e9}\u{1f600}\r\n";
+        let byte_offsets = [0, 1, 3, 7, 8, 9, 10];
+        for (chars, &bytes) in byte_offsets.iter().enumerate() {
+            assert_eq!(char_index(content, bytes), chars);
+            assert_eq!(byte_index(content, chars), bytes);
+        }
+    }
+
+    #[test]
+    fn test_manager_run_unicode() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "line = content[content_index:].split('\\n', 1)[0] + '\\n'\nnew_content += line.upper()\ncontent_index += len(line)",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-2.toml"),
+            "kind = 'set-data'\npattern = '^(\\w+)'\nkey = 'word'",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-3.py"),
+            "data_map['index'] = str(content_index)\nnew_content += content[content_index:]\ncontent_index = len(content)",
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let full_content = "h\u{e9}llo \u{1f600}\r\nw\u{f6}rld\r\n";
+        let expected = "H\u{c9}LLO \u{1f600}\r\nw\u{f6}rld\r\n";
+        for mode in [ExecutionMode::Combined, ExecutionMode::Isolated] {
+            manager.set_execution_mode(mode);
+            let output = manager.run_all_operations(full_content, "python3").unwrap();
+            assert_eq!(output.new_content, expected);
+            assert_eq!(output.data_map["word"], "w\u{f6}rld");
+            assert_eq!(output.data_map["index"], "9");
+            assert_eq!(output.content_index, full_content.len());
+        }
+
+        let trace = manager
+            .trace_operations(3, full_content, "python3")
+            .unwrap();
+        assert_eq!(trace.steps[0].content_index, 9);
+        assert_eq!(trace.steps[2].content_index, 16);
+        let mut worker = manager.start_worker(1, "python3").unwrap();
+        let output = worker
+            .run_json(r#"{"data_map": {}, "full_content": "\ud83d\ude00\r\nw\u00f6rld\r\n", "content_index": 3}"#)
+            .unwrap();
+        assert_eq!(output.new_content, "W\u{d6}RLD\r\n");
+        assert_eq!(output.content_index, 10);
+
+        // the length of the content is reported in code points too
+        manager.remove_operation(3).unwrap();
+        let result = manager.run_all_operations(full_content, "python3");
+        match result {
+            Err(Error::IncompleteConsumption { index, len }) => {
+                assert_eq!(index, 9);
+                assert_eq!(len, 16);
+            }
+            r => panic!("Expected IncompleteConsumption, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_manager_run_binary() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "data_map['first'] = str(content[0])\nnew_content += content.replace(b'a', b'b')\ncontent_index = len(content)",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("opt-2.toml"),
+            "kind = 'replace'\npattern = 'b'\nreplacement = 'c'\napply_to = 'output'",
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        manager.set_binary(true);
+        let full_content = crate::bytes_to_content(&[0xff, 0xfe, b'a', 0, 0x80, b'\n']);
+        let output = manager
+            .run_all_operations(&full_content, "python3")
+            .unwrap();
+        assert_eq!(
+            crate::content_to_bytes(&output.new_content).unwrap(),
+            [0xff, 0xfe, b'c', 0, 0x80, b'\n']
+        );
+        assert_eq!(output.data_map["first"], "255");
+
+        let mut worker = manager.start_worker(2, "python3").unwrap();
+        let output = worker
+            .run_json(r#"{"data_map": {}, "full_content": "/2E=", "content_index": 0}"#)
+            .unwrap();
+        assert_eq!(output.new_content, "/2M=");
+        assert_eq!(output.content_index, 2);
+    }
+
+    #[test]
+    fn test_manager_run_shell() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "new_content += content.upper()\ncontent_index += len(content)",
+        )
+        .unwrap();
+        // the input is read from stdin, and what is printed is not the output
+        fs::write(
+            temp_dir.path().join("opt-2.sh"),
+            "echo running\npython3 -c 'import json, os, sys\nd = json.load(sys.stdin)\nd[\"data_map\"][\"shell\"] = \"yes\"\nd[\"new_content\"] += \"!\"\nd[\"error_message\"] = \"\"\njson.dump(d, open(os.environ[\"OUTPUT_FILE\"], \"w\"))'",
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let output = manager.run_all_operations("Hello", "python3").unwrap();
+        assert_eq!(output.new_content, "HELLO!");
+        assert_eq!(output.data_map["shell"], "yes");
+        let mut worker = manager.start_worker(2, "python3").unwrap();
+        let output = worker
+            .run_json(r#"{"data_map": {}, "full_content": "abc", "content_index": 0}"#)
+            .unwrap();
+        assert_eq!(output.new_content, "ABC!");
+
+        fs::write(temp_dir.path().join("opt-2.sh"), "echo broken >&2\nexit 3").unwrap();
+        match manager.run_all_operations("Hello", "python3") {
+            Err(e @ Error::CommandFailed { .. }) => {
+                assert_eq!(
+                    e.to_string(),
+                    "Step 2 (opt-2.sh) exited with status 3:\nbroken"
+                );
+            }
+            r => panic!("Expected CommandFailed, got {:?}", r),
+        }
+    }
+
+    struct UpperRuntime;
+
+    impl Runtime for UpperRuntime {
+        fn extension(&self) -> &str {
+            "upper"
+        }
+
+        fn run(
+            &self,
+            _opts: &[Operation],
+            data: &OperationData,
+            _context: &RunContext,
+        ) -> Result<OperationOutput, Error> {
+            let mut output = OperationOutput::from_data(data);
+            output
+                .new_content
+                .push_str(&data.full_content[data.content_index..].to_uppercase());
+            output.content_index = data.full_content.len();
+            Ok(output)
+        }
+    }
+
+    #[test]
+    fn test_manager_register_runtime() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("opt-1.upper"), "").unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        assert!(manager.get_operation(1).is_none());
+        manager.register_runtime(Arc::new(UpperRuntime));
+        assert_eq!(manager.get_operation(1).unwrap().extension(), "upper");
+        let output = manager.run_all_operations("Hello", "python3").unwrap();
+        assert_eq!(output.new_content, "HELLO");
+    }
+
+    #[test]
+    fn test_manager_run_operation_errors() {
+        // create a temporary directory
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        let opt1 = manager.insert_operation(1).unwrap();
+        assert!(
+            opt1.user_write_content(&get_operation_temple_python(Some("data_map['step'] = '1'")))
+        );
+        let opt2 = manager.insert_operation(2).unwrap();
+        assert!(opt2.user_write_content(&get_operation_temple_python(Some(
+            "error_message = 'failed'"
+        ))));
+
+        let result = manager.run_all_operations("Hello", "python3");
+        match result {
+            Err(Error::OperationReported { id, message }) => {
+                assert_eq!(id, Some(2));
+                assert_eq!(message, "failed");
+            }
+            r => panic!("Expected OperationReported, got {:?}", r),
+        }
+
+        manager.remove_operation(2).unwrap();
+        let result = manager.run_all_operations("Hello", "python3");
+        match result {
+            Err(Error::IncompleteConsumption { index, len }) => {
+                assert_eq!(index, 0);
+                assert_eq!(len, 5);
+            }
+            r => panic!("Expected IncompleteConsumption, got {:?}", r),
+        }
+
+        let data_map = HashMap::new();
+        let data = OperationData {
+            data_map: &data_map,
+            full_content: "Hello",
+            content_index: 0,
+            new_content: "",
+        };
+        let context = RunContext {
+            python_runner: "python3",
+            limits: Limits::default(),
+            sandboxed: false,
+            binary: false,
+        };
+        let result = run_opts(
+            &[Operation::new(5, opt_dir_path)],
+            &data,
+            &Runtimes::default(),
+            &context,
+        );
+        match result {
+            Err(Error::OperationNotFound { id }) => assert_eq!(id, 5),
+            r => panic!("Expected OperationNotFound, got {:?}", r),
+        }
+    }
+}
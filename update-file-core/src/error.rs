@@ -0,0 +1,182 @@
+use std::{fmt, io};
+
+use crate::limits::Limit;
+
+/// Everything that can go wrong while loading or running operations.
+#[derive(Debug)]
+pub enum Error {
+    /// The operation file does not exist in the opt dir.
+    OperationNotFound {
+        id: usize,
+    },
+    /// The interpreter exited unsuccessfully, e.g. because the operation raised.
+    InterpreterFailed {
+        stderr: String,
+        exit_code: Option<i32>,
+    },
+    /// A run of a [`crate::OperationWorker`] raised outside of the operations,
+    /// e.g. on an invalid input. The worker keeps running.
+    WorkerRunFailed {
+        stderr: String,
+    },
+    /// An operation raised while running, located in its own file.
+    OperationFailed {
+        id: usize,
+        file: String,
+        line: usize,
+        message: String,
+        stderr: String,
+    },
+    /// The interpreter output does not follow the `OperationOutput` protocol.
+    ProtocolDecode(serde_json::Error),
+    /// An operation set `error_message`. `id` is the one that set it, if
+    /// known: an output given from outside may not tell.
+    OperationReported {
+        id: Option<usize>,
+        message: String,
+    },
+    /// A native operation file is not valid.
+    InvalidOperation {
+        id: usize,
+        file: String,
+        message: String,
+    },
+    /// An operation run as its own process exited unsuccessfully.
+    CommandFailed {
+        id: usize,
+        file: String,
+        stderr: String,
+        exit_code: Option<i32>,
+    },
+    /// An operation run by an embedded engine aborted.
+    OperationTrapped {
+        id: usize,
+        file: String,
+        message: String,
+    },
+    /// An operation ran longer or used more than its limits allow.
+    LimitExceeded {
+        id: usize,
+        file: String,
+        limit: Limit,
+    },
+    /// An operation run with limits was killed by `signal`, the way a limit
+    /// would stop it, without having used up its CPU time.
+    KilledBySignal {
+        id: usize,
+        file: String,
+        signal: i32,
+    },
+    /// An operation could not be started in a sandbox.
+    SandboxFailed(io::Error),
+    /// The `pipeline.toml` manifest could not be read or written.
+    ManifestDecode(String),
+    /// The operations did not consume the whole content.
+    IncompleteConsumption {
+        index: usize,
+        len: usize,
+    },
+    /// Content in byte mode has a character that does not stand for a byte.
+    NotBytes(char),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OperationNotFound { id } => write!(f, "Operation {} content is not found", id),
+            Error::InterpreterFailed { stderr, exit_code } => {
+                match exit_code {
+                    Some(code) => write!(f, "Interpreter exited with status {}", code)?,
+                    None => write!(f, "Interpreter was terminated by a signal")?,
+                }
+                if stderr.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, ":\n{}", stderr.trim_end())
+                }
+            }
+            Error::WorkerRunFailed { stderr } => {
+                write!(f, "Worker run failed:\n{}", stderr.trim_end())
+            }
+            Error::OperationFailed {
+                id,
+                file,
+                line,
+                message,
+                ..
+            } => write!(f, "step {} ({}:{}): {}", id, file, line, message),
+            Error::ProtocolDecode(e) => write!(f, "Operation output conversion failed: {}", e),
+            Error::OperationReported {
+                id: Some(id),
+                message,
+            } => write!(f, "Error from operation {}: {}", id, message),
+            Error::OperationReported { id: None, message } => {
+                write!(f, "Error from an operation: {}", message)
+            }
+            Error::InvalidOperation { id, file, message } => {
+                write!(f, "Invalid operation {} ({}): {}", id, file, message)
+            }
+            Error::CommandFailed {
+                id,
+                file,
+                stderr,
+                exit_code,
+            } => {
+                match exit_code {
+                    Some(code) => write!(f, "Step {} ({}) exited with status {}", id, file, code)?,
+                    None => write!(f, "Step {} ({}) was terminated by a signal", id, file)?,
+                }
+                if stderr.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, ":\n{}", stderr.trim_end())
+                }
+            }
+            Error::OperationTrapped { id, file, message } => {
+                write!(f, "Step {} ({}) trapped: {}", id, file, message)
+            }
+            Error::LimitExceeded { id, file, limit } => {
+                write!(f, "Step {} ({}) exceeded its {}", id, file, limit)
+            }
+            Error::KilledBySignal { id, file, signal } => {
+                write!(f, "Step {} ({}) was killed by signal {}", id, file, signal)
+            }
+            Error::SandboxFailed(e) => write!(f, "Could not set up the sandbox: {}", e),
+            Error::ManifestDecode(e) => write!(f, "Invalid pipeline manifest: {}", e),
+            Error::IncompleteConsumption { index, len } => write!(
+                f,
+                "Content index {} is not equal to the length of full content {}",
+                index, len
+            ),
+            Error::NotBytes(c) => write!(
+                f,
+                "Content in byte mode has the character {:?}, which is not a byte",
+                c
+            ),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ProtocolDecode(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::ProtocolDecode(e)
+    }
+}
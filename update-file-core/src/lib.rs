@@ -0,0 +1,51 @@
+//! Load a pipeline of operations from an opt dir and run it on a piece of content.
+//!
+//! An opt dir holds one file per operation (`opt-1.py`, `opt-2.py`, ...), and
+//! optionally a [`Manifest`] (`pipeline.toml`) that names the steps and sets
+//! their order. Operations are Python snippets, built-in [`NativeOperation`]s
+//! declared in `.toml` files, `.rhai` scripts run by an embedded engine (with
+//! the default `rhai` feature), sandboxed `.wasm` modules (with the default
+//! `wasm` feature), or `.sh` scripts; the extension of an operation
+//! file picks the [`Runtime`] that runs it. The operations are run in order on
+//! the source content; together they build the new content and must consume
+//! the whole source. Bytes that are not text can be run on in byte mode, see
+//! [`OperationManager::set_binary`].
+//!
+//! ```no_run
+//! use update_file_core::OperationManager;
+//!
+//! let mut manager = OperationManager::new("path/to/opt");
+//! let output = manager.run_all_operations("Hello, World!", "python3").unwrap();
+//! println!("{}", output.new_content);
+//! ```
+mod binary;
+mod core;
+mod error;
+mod limits;
+mod manifest;
+mod native;
+mod python;
+mod renumber;
+mod runtime;
+mod sandbox;
+#[cfg(feature = "rhai")]
+mod script;
+pub mod trace;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use crate::binary::{bytes_to_content, content_to_bytes};
+pub use crate::core::{
+    check_output, ExecutionMode, Operation, OperationData, OperationManager, OperationOutput,
+    OperationWorker,
+};
+pub use crate::error::Error;
+pub use crate::limits::{Limit, Limits};
+pub use crate::manifest::{Manifest, ManifestStep, MANIFEST_FILE_NAME};
+pub use crate::native::{ApplyTo, NativeOperation};
+pub use crate::runtime::{NativeRuntime, PythonRuntime, RunContext, Runtime, ShellRuntime};
+#[cfg(feature = "rhai")]
+pub use crate::script::RhaiRuntime;
+pub use crate::trace::Trace;
+#[cfg(feature = "wasm")]
+pub use crate::wasm::WasmRuntime;
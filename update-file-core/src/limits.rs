@@ -0,0 +1,738 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Output};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+
+use crate::core::Operation;
+use crate::error::Error;
+use crate::sandbox::{temp_file, Sandbox};
+
+/// Limits of an operation, applied to its process or checked by the embedded
+/// engine running it. In a manifest step they are given as `timeout` and
+/// `cpu_time` in seconds and `memory_mb` in MiB:
+///
+/// ```toml
+/// [[step]]
+/// name = "slow"
+/// file = "slow.py"
+/// timeout = 30
+/// memory_mb = 512
+/// ```
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Wall-clock time, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+    /// CPU time, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time: Option<f64>,
+    /// Address space of the process, in MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+}
+
+impl Limits {
+    /// Each limit of `self`, or of `defaults` where `self` has none.
+    pub fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            timeout: self.timeout.or(defaults.timeout),
+            cpu_time: self.cpu_time.or(defaults.cpu_time),
+            memory_mb: self.memory_mb.or(defaults.memory_mb),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+}
+
+/// A limit an operation exceeded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Timeout(f64),
+    CpuTime(f64),
+    Memory(u64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Timeout(seconds) => write!(f, "timeout of {}s", seconds),
+            Limit::CpuTime(seconds) => write!(f, "CPU time limit of {}s", seconds),
+            Limit::Memory(mb) => write!(f, "memory limit of {} MiB", mb),
+        }
+    }
+}
+
+/// How often a process with a timeout is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct StepLimits {
+    id: usize,
+    file: String,
+    limits: Limits,
+}
+
+/// The limits of a step in `STEP_LIMITS`.
+#[derive(Serialize, Debug, PartialEq)]
+struct StepLimitsJson {
+    #[serde(flatten)]
+    limits: Limits,
+    cpu_time_rest: Option<f64>,
+    memory_mb_rest: Option<u64>,
+}
+
+/// Limits of the operations run by one process. When it runs several, the
+/// process writes the id of the running one and the CPU time it used before
+/// it to the file named by the `PROGRESS_FILE` environment variable, so the
+/// timeout of each operation counts from its own start, and a violation names
+/// the operation running. The process then also gets the limits of each
+/// operation as JSON in the `STEP_LIMITS` environment variable, and sets the
+/// CPU time and memory limits of an operation itself as it starts it, see
+/// [`ProcessLimits::step_limits`].
+#[derive(Default)]
+pub(crate) struct ProcessLimits {
+    steps: Vec<StepLimits>,
+    progress: Option<NamedTempFile>,
+}
+
+fn read_in_thread<R: Read + Send + 'static>(mut reader: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+impl ProcessLimits {
+    pub fn new(
+        opts: &[Operation],
+        defaults: Limits,
+        sandbox: Option<&Sandbox>,
+    ) -> Result<ProcessLimits, Error> {
+        let steps = opts
+            .iter()
+            .map(|opt| StepLimits {
+                id: opt.id(),
+                file: opt.file_name().to_string(),
+                limits: opt.limits().or(defaults),
+            })
+            .collect::<Vec<_>>();
+        let progress = if steps.len() > 1 && steps.iter().any(|s| !s.limits.is_empty()) {
+            Some(temp_file(sandbox)?)
+        } else {
+            None
+        };
+        Ok(ProcessLimits { steps, progress })
+    }
+
+    /// The CPU time and memory limits set on the process when it starts, those
+    /// of its only operation. A process running several sets them itself.
+    fn process_limits(&self) -> Limits {
+        match (&self.progress, self.steps.as_slice()) {
+            (None, [step]) => step.limits,
+            _ => Limits::default(),
+        }
+    }
+
+    fn has_timeout(&self) -> bool {
+        self.steps.iter().any(|s| s.limits.timeout.is_some())
+    }
+
+    /// Set up `command` to run with the limits.
+    pub fn apply(&self, command: &mut Command) {
+        if let Some(progress) = &self.progress {
+            command.env("PROGRESS_FILE", progress.path());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+
+            if self.progress.is_some() {
+                command.env(
+                    "STEP_LIMITS",
+                    serde_json::to_string(&self.step_limits()).unwrap(),
+                );
+            }
+            if self.has_timeout() {
+                // its own process group, so a timeout also stops what it started
+                command.process_group(0);
+            }
+            let limits = self.process_limits();
+            let cpu_time = limits
+                .cpu_time
+                .map(|seconds| seconds.ceil() as libc::rlim_t);
+            let memory = limits
+                .memory_mb
+                .map(|mb| mb.saturating_mul(1024 * 1024) as libc::rlim_t);
+            if cpu_time.is_some() || memory.is_some() {
+                // SAFETY: setrlimit is async-signal-safe, and nothing is allocated
+                unsafe {
+                    command.pre_exec(move || {
+                        if let Some(seconds) = cpu_time {
+                            // SIGXCPU at the limit, SIGKILL if it is ignored
+                            set_rlimit(libc::RLIMIT_CPU, seconds, seconds.saturating_add(1))?;
+                        }
+                        if let Some(bytes) = memory {
+                            set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
+                        }
+                        Ok(())
+                    });
+                }
+            }
+        }
+    }
+
+    /// The limits of each step by id, for a process running several, with
+    /// the most the steps from it on may use in all. The process sets these
+    /// as the hard limits as the step starts, so an operation that raises its
+    /// own limits gets no more than the steps after it are allowed: it can
+    /// only lift a limit that a later step does not have.
+    fn step_limits(&self) -> HashMap<usize, StepLimitsJson> {
+        let mut step_limits = HashMap::new();
+        let mut cpu_time_rest = Some(0.0);
+        let mut memory_mb_rest = Some(0);
+        for step in self.steps.iter().rev() {
+            // each limit is rounded up to whole seconds as the step starts
+            cpu_time_rest = cpu_time_rest
+                .zip(step.limits.cpu_time)
+                .map(|(rest, seconds)| rest + seconds + 1.0);
+            memory_mb_rest = memory_mb_rest
+                .zip(step.limits.memory_mb)
+                .map(|(rest, mb)| rest.max(mb));
+            step_limits.insert(
+                step.id,
+                StepLimitsJson {
+                    limits: step.limits,
+                    cpu_time_rest,
+                    memory_mb_rest,
+                },
+            );
+        }
+        step_limits
+    }
+
+    /// Index of the step the process is at, and the CPU time in seconds the
+    /// process used before it.
+    fn current_step(&self) -> (usize, f64) {
+        let progress = self
+            .progress
+            .as_ref()
+            .and_then(|progress| fs::read_to_string(progress.path()).ok())
+            .unwrap_or_default();
+        let mut parts = progress.split_whitespace();
+        let step = parts
+            .next()
+            .and_then(|id| id.parse::<usize>().ok())
+            .and_then(|id| self.steps.iter().position(|s| s.id == id))
+            .unwrap_or_default();
+        let cpu_time = parts
+            .next()
+            .and_then(|seconds| seconds.parse::<f64>().ok())
+            .unwrap_or_default();
+        (step, cpu_time)
+    }
+
+    fn exceeded(&self, step: usize, limit: Limit) -> Error {
+        let step = &self.steps[step];
+        Error::LimitExceeded {
+            id: step.id,
+            file: step.file.clone(),
+            limit,
+        }
+    }
+
+    /// Like [`Child::wait_with_output`], but stops the process at a timeout,
+    /// and reports a violated limit as [`Error::LimitExceeded`].
+    pub fn wait(&self, mut child: Child) -> Result<Output, Error> {
+        let stdout = child.stdout.take().map(read_in_thread);
+        let stderr = child.stderr.take().map(read_in_thread);
+        let (status, cpu_time) = if self.has_timeout() {
+            self.wait_with_timeout(&mut child)?
+        } else {
+            wait_child(&mut child, true)?.unwrap()
+        };
+        let output = Output {
+            status,
+            stdout: stdout.map_or_else(Vec::new, |h| h.join().unwrap()),
+            stderr: stderr.map_or_else(Vec::new, |h| h.join().unwrap()),
+        };
+        if !output.status.success() && !self.steps.is_empty() {
+            if let Some(e) = self.violation(&output, cpu_time) {
+                return Err(e);
+            }
+        }
+        Ok(output)
+    }
+
+    fn wait_with_timeout(
+        &self,
+        child: &mut Child,
+    ) -> Result<(ExitStatus, Option<Duration>), Error> {
+        let (mut step, _) = self.current_step();
+        let mut started = Instant::now();
+        loop {
+            if let Some(waited) = wait_child(child, false)? {
+                return Ok(waited);
+            }
+            let (current, _) = self.current_step();
+            if current != step {
+                step = current;
+                started = Instant::now();
+            }
+            if let Some(timeout) = self.steps[step].limits.timeout {
+                if started.elapsed().as_secs_f64() > timeout {
+                    kill(child);
+                    wait_child(child, true)?;
+                    return Err(self.exceeded(step, Limit::Timeout(timeout)));
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// The error of a process that failed because of a limit, or was killed
+    /// the way a limit kills it. `cpu_time` is the CPU time it used in all.
+    fn violation(&self, output: &Output, cpu_time: Option<Duration>) -> Option<Error> {
+        let (step, cpu_start) = self.current_step();
+        let limits = self.steps[step].limits;
+        if limits.is_empty() {
+            return None;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            if let Some(signal) = output.status.signal() {
+                if signal != libc::SIGXCPU && signal != libc::SIGKILL {
+                    return None;
+                }
+                // only a step that did use up its CPU time was stopped for it
+                let used = cpu_time.map(|total| total.as_secs_f64() - cpu_start);
+                return Some(match (limits.cpu_time, used) {
+                    (Some(seconds), Some(used)) if used >= seconds => {
+                        self.exceeded(step, Limit::CpuTime(seconds))
+                    }
+                    _ => Error::KilledBySignal {
+                        id: self.steps[step].id,
+                        file: self.steps[step].file.clone(),
+                        signal,
+                    },
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (cpu_time, cpu_start);
+        let mb = limits.memory_mb?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let last_line = stderr.lines().rev().find(|l| !l.trim().is_empty())?;
+        last_line
+            .trim()
+            .starts_with("MemoryError")
+            .then(|| self.exceeded(step, Limit::Memory(mb)))
+    }
+}
+
+/// Like [`Child::try_wait`], or [`Child::wait`] if `block`, also returning the
+/// CPU time used by the process and the children it waited for, where known.
+#[cfg(unix)]
+fn wait_child(
+    child: &mut Child,
+    block: bool,
+) -> io::Result<Option<(ExitStatus, Option<Duration>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: rusage is plain data, valid when zeroed
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let options = if block { 0 } else { libc::WNOHANG };
+    loop {
+        // SAFETY: both pointers are to valid locals
+        let pid =
+            unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, options, &mut usage) };
+        match pid {
+            0 => return Ok(None),
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            _ => break,
+        }
+    }
+    let seconds = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    let cpu_time = seconds(usage.ru_utime) + seconds(usage.ru_stime);
+    Ok(Some((ExitStatus::from_raw(status), Some(cpu_time))))
+}
+
+#[cfg(not(unix))]
+fn wait_child(
+    child: &mut Child,
+    block: bool,
+) -> io::Result<Option<(ExitStatus, Option<Duration>)>> {
+    let status = if block {
+        Some(child.wait()?)
+    } else {
+        child.try_wait()?
+    };
+    Ok(status.map(|status| (status, None)))
+}
+
+/// Time limits of an operation run inside this process by an embedded engine,
+/// which checks them from time to time while the operation runs. Its CPU time
+/// is that of the thread running it.
+#[cfg(any(feature = "rhai", feature = "wasm"))]
+pub(crate) struct Budget {
+    limits: Limits,
+    started: Instant,
+    cpu_started: Option<Duration>,
+}
+
+#[cfg(any(feature = "rhai", feature = "wasm"))]
+impl Budget {
+    pub fn start(limits: Limits) -> Budget {
+        Budget {
+            limits,
+            started: Instant::now(),
+            cpu_started: thread_cpu_time(),
+        }
+    }
+
+    /// The limit the operation ran past so far, if any.
+    pub fn exceeded(&self) -> Option<Limit> {
+        if let Some(seconds) = self.limits.timeout {
+            if self.started.elapsed().as_secs_f64() > seconds {
+                return Some(Limit::Timeout(seconds));
+            }
+        }
+        let seconds = self.limits.cpu_time?;
+        let used = thread_cpu_time()?.checked_sub(self.cpu_started?)?;
+        (used.as_secs_f64() > seconds).then_some(Limit::CpuTime(seconds))
+    }
+}
+
+/// CPU time used by the current thread, where known.
+#[cfg(any(feature = "rhai", feature = "wasm"))]
+fn thread_cpu_time() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: the pointer is to a valid timespec
+        if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } == 0 {
+            return Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32));
+        }
+    }
+    None
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn set_rlimit(resource: Resource, soft: libc::rlim_t, hard: libc::rlim_t) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft,
+        rlim_max: hard,
+    };
+    // SAFETY: the pointer is to a valid rlimit
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        // SAFETY: plain system call; the group is the one made in apply
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+    }
+    let _ = child.kill();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationManager;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_limits_or() {
+        let global = Limits {
+            timeout: Some(10.0),
+            cpu_time: None,
+            memory_mb: Some(100),
+        };
+        let step = Limits {
+            timeout: Some(1.0),
+            ..Limits::default()
+        };
+        assert_eq!(
+            step.or(global),
+            Limits {
+                timeout: Some(1.0),
+                cpu_time: None,
+                memory_mb: Some(100),
+            }
+        );
+        assert!(Limits::default().is_empty());
+    }
+
+    #[test]
+    fn test_step_limits_rest() {
+        let step = |id: usize, cpu_time: Option<f64>, memory_mb: Option<u64>| StepLimits {
+            id,
+            file: format!("opt-{}.py", id),
+            limits: Limits {
+                timeout: None,
+                cpu_time,
+                memory_mb,
+            },
+        };
+        let limits = ProcessLimits {
+            steps: vec![
+                step(1, None, Some(64)),
+                step(2, Some(1.0), Some(256)),
+                step(3, Some(2.0), Some(32)),
+            ],
+            progress: None,
+        };
+        let step_limits = limits.step_limits();
+        // a step without a limit cannot be held to one
+        assert_eq!(step_limits[&1].cpu_time_rest, None);
+        assert_eq!(step_limits[&1].memory_mb_rest, Some(256));
+        assert_eq!(step_limits[&2].cpu_time_rest, Some(5.0));
+        assert_eq!(step_limits[&2].memory_mb_rest, Some(256));
+        assert_eq!(step_limits[&3].cpu_time_rest, Some(3.0));
+        assert_eq!(step_limits[&3].memory_mb_rest, Some(32));
+        assert_eq!(
+            serde_json::to_value(&step_limits[&3]).unwrap(),
+            serde_json::json!({"cpu_time": 2.0, "memory_mb": 32, "cpu_time_rest": 3.0, "memory_mb_rest": 32})
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "import time\ntime.sleep(0.3)",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("opt-2.py"), "while True:\n    pass").unwrap();
+        fs::write(temp_dir.path().join("opt-3.sh"), "sleep 10").unwrap();
+
+        // the timeout counts for each operation, not for the whole script
+        let mut manager = OperationManager::new(opt_dir_path);
+        manager.set_limits(Limits {
+            timeout: Some(0.5),
+            ..Limits::default()
+        });
+        let started = Instant::now();
+        match manager.run_operations(2, "Hello", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 2 (opt-2.py) exceeded its timeout of 0.5s"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        fs::remove_file(temp_dir.path().join("opt-2.py")).unwrap();
+        fs::rename(
+            temp_dir.path().join("opt-3.sh"),
+            temp_dir.path().join("opt-2.sh"),
+        )
+        .unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            manager.run_operations(2, "Hello", "python3"),
+            Err(Error::LimitExceeded {
+                id: 2,
+                limit: Limit::Timeout(_),
+                ..
+            })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // a step of the manifest sets its own limits
+        fs::write(
+            temp_dir.path().join("pipeline.toml"),
+            "[[step]]\nname = 'sleep'\nfile = 'opt-1.py'\ntimeout = 0.1\n",
+        )
+        .unwrap();
+        manager.set_limits(Limits::default());
+        match manager.run_operations(1, "Hello", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 1 (opt-1.py) exceeded its timeout of 0.1s"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cpu_and_memory_limits() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("opt-1.py"), "while True:\n    pass").unwrap();
+        let mut manager = OperationManager::new(opt_dir_path);
+        manager.set_limits(Limits {
+            cpu_time: Some(1.0),
+            ..Limits::default()
+        });
+        match manager.run_operations(1, "Hello", "python3") {
+            Err(Error::LimitExceeded { id, limit, .. }) => {
+                assert_eq!(id, 1);
+                assert_eq!(limit, Limit::CpuTime(1.0));
+            }
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            "data = bytearray(1024 ** 3)",
+        )
+        .unwrap();
+        manager.set_limits(Limits {
+            memory_mb: Some(256),
+            ..Limits::default()
+        });
+        match manager.run_operations(1, "Hello", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 1 (opt-1.py) exceeded its memory limit of 256 MiB"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_step_limits_combined() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join("free.py"),
+            "import time\ndata = bytearray(512 * 1024 ** 2)\ndel data\nwhile time.process_time() < 1.5:\n    pass",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("busy.py"), "while True:\n    pass").unwrap();
+        fs::write(
+            temp_dir.path().join("big.py"),
+            "data = bytearray(1024 ** 3)",
+        )
+        .unwrap();
+        let manifest = |file: &str, limit: &str| {
+            fs::write(
+                temp_dir.path().join("pipeline.toml"),
+                format!(
+                    "[[step]]\nname = 'free'\nfile = 'free.py'\n\n[[step]]\nname = 'limited'\nfile = '{}'\n{}\n",
+                    file, limit
+                ),
+            )
+            .unwrap();
+        };
+
+        // only the step with limits is held to them, each to its own value
+        let mut manager = OperationManager::new(opt_dir_path);
+        manifest("busy.py", "cpu_time = 1");
+        match manager.run_operations(2, "Hello", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 2 (busy.py) exceeded its CPU time limit of 1s"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+        manifest("big.py", "memory_mb = 256");
+        match manager.run_operations(2, "Hello", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 2 (big.py) exceeded its memory limit of 256 MiB"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+
+        // a step cannot lift its limit above what the steps after it may use
+        fs::write(
+            temp_dir.path().join("lift.py"),
+            r#"import resource
+try:
+    resource.setrlimit(resource.RLIMIT_AS, (resource.RLIM_INFINITY,) * 2)
+    data_map["lifted"] = "yes"
+except ValueError:
+    data_map["lifted"] = "no"
+data_map["hard"] = str(resource.getrlimit(resource.RLIMIT_AS)[1])"#,
+        )
+        .unwrap();
+        manifest("lift.py", "");
+        manager.set_limits(Limits {
+            memory_mb: Some(1024),
+            ..Limits::default()
+        });
+        let output = manager.run_operations(2, "Hello", "python3").unwrap();
+        assert_eq!(output.data_map["lifted"], "no");
+        assert_eq!(output.data_map["hard"], (1024 * 1024 * 1024).to_string());
+
+        // every step has the limit given for all, not their sum
+        manifest("busy.py", "");
+        manager.set_limits(Limits {
+            cpu_time: Some(2.0),
+            ..Limits::default()
+        });
+        match manager.run_operations(2, "Hello", "python3") {
+            Err(Error::LimitExceeded { id, limit, .. }) => {
+                assert_eq!(id, 2);
+                assert_eq!(limit, Limit::CpuTime(2.0));
+            }
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_killed_by_signal() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let mut manager = OperationManager::new(opt_dir_path);
+        manager.set_limits(Limits {
+            cpu_time: Some(10.0),
+            ..Limits::default()
+        });
+        // a kill is only reported as the CPU time limit if that was used up
+        for (file, code) in [
+            (
+                "opt-1.py",
+                "import os, signal\nos.kill(os.getpid(), signal.SIGKILL)",
+            ),
+            ("opt-1.sh", "kill -KILL $$"),
+        ] {
+            for entry in fs::read_dir(temp_dir.path()).unwrap() {
+                fs::remove_file(entry.unwrap().path()).unwrap();
+            }
+            fs::write(temp_dir.path().join(file), code).unwrap();
+            match manager.run_operations(1, "Hello", "python3") {
+                Err(e @ Error::KilledBySignal { .. }) => assert_eq!(
+                    e.to_string(),
+                    format!("Step 1 ({}) was killed by signal {}", file, libc::SIGKILL)
+                ),
+                r => panic!("Expected KilledBySignal, got {:?}", r),
+            }
+        }
+    }
+}
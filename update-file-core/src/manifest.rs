@@ -0,0 +1,388 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+use tempfile::NamedTempFile;
+use toml_edit::{ArrayOfTables, Decor, DocumentMut, Item, Table, Value};
+
+use crate::error::Error;
+use crate::limits::Limits;
+
+pub const MANIFEST_FILE_NAME: &str = "pipeline.toml";
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+/// A step of the pipeline as listed in the manifest.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestStep {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Path of the operation file, relative to the opt dir and inside it.
+    pub file: String,
+    /// Limits of this step, over the ones set for the whole run.
+    #[serde(default, flatten)]
+    pub limits: Limits,
+}
+
+/// Optional `pipeline.toml` in the opt dir. When it exists, the steps run in
+/// the order they are listed, whatever their file names are:
+///
+/// ```toml
+/// [[step]]
+/// name = "detect-start"
+/// description = "Remember the first line"
+/// file = "opt-1.py"
+///
+/// [[step]]
+/// name = "lowercase"
+/// file = "lowercase.py"
+/// enabled = false
+/// timeout = 5
+/// ```
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct Manifest {
+    #[serde(default, rename = "step")]
+    pub steps: Vec<ManifestStep>,
+}
+
+/// Refuse a step `file` outside the opt dir, which removing the step would
+/// delete: absolute paths, `..` and symlinks leading out of it.
+fn check_file(opt_dir_path: &str, file: &str) -> Result<(), Error> {
+    let mut components = Path::new(file).components();
+    let mut inside = components
+        .clone()
+        .any(|c| matches!(c, Component::Normal(_)))
+        && components.all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if let (Ok(dir), Ok(path)) = (
+        fs::canonicalize(opt_dir_path),
+        fs::canonicalize(Path::new(opt_dir_path).join(file)),
+    ) {
+        inside &= path.starts_with(dir);
+    }
+    if inside {
+        Ok(())
+    } else {
+        Err(Error::ManifestDecode(format!(
+            "step file {} is outside the opt dir",
+            file
+        )))
+    }
+}
+
+/// Set the values of `table` to the ones of `new`, leaving the values that did
+/// not change as they are written.
+fn update_table(table: &mut Table, new: Table) {
+    table.retain(|key, _| new.contains_key(key));
+    for (key, item) in new {
+        match (table.get_mut(&key).and_then(Item::as_value_mut), item) {
+            (Some(old), Item::Value(value)) => {
+                if !same_value(old, &value) {
+                    let decor = old.decor().clone();
+                    *old = value;
+                    *old.decor_mut() = decor;
+                }
+            }
+            (_, item) => {
+                table.insert(&key, item);
+            }
+        }
+    }
+}
+
+fn decor_prefix(decor: &Decor) -> &str {
+    decor
+        .prefix()
+        .and_then(|prefix| prefix.as_str())
+        .unwrap_or_default()
+}
+
+/// Whether two values are equal, whatever their formatting, e.g. `5` and
+/// `5.0`, or a literal and a basic string.
+fn same_value(a: &Value, b: &Value) -> bool {
+    let number = |v: &Value| v.as_float().or(v.as_integer().map(|i| i as f64));
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        _ => number(a).is_some() && number(a) == number(b),
+    }
+}
+
+impl Manifest {
+    /// Load the manifest of `opt_dir_path`, or `None` if it has none.
+    pub fn load(opt_dir_path: &str) -> Result<Option<Manifest>, Error> {
+        let path = Path::new(opt_dir_path).join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        let manifest: Manifest =
+            toml::from_str(&content).map_err(|e| Error::ManifestDecode(e.to_string()))?;
+        for step in &manifest.steps {
+            check_file(opt_dir_path, &step.file)?;
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Write the manifest to `opt_dir_path`. An existing manifest is edited in
+    /// place, keeping its comments and formatting: each step keeps its table,
+    /// found by its file, and only the values that changed are written again.
+    pub fn save(&self, opt_dir_path: &str) -> Result<(), Error> {
+        let path = Path::new(opt_dir_path).join(MANIFEST_FILE_NAME);
+        let mut document = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .parse::<DocumentMut>()
+                .map_err(|e| Error::ManifestDecode(e.to_string()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => DocumentMut::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut old_tables: Vec<Table> = match document.remove("step") {
+            Some(Item::ArrayOfTables(tables)) => tables.into_iter().collect(),
+            _ => Vec::new(),
+        };
+        // the comments at the top of the file, up to the last blank line
+        // before the first step, stay there when that step moves
+        if let Some(first) = old_tables.first_mut() {
+            let prefix = decor_prefix(first.decor()).to_string();
+            if let Some(end) = prefix.rfind("\n\n") {
+                let head = format!("{}{}", decor_prefix(document.decor()), &prefix[..end + 2]);
+                document.decor_mut().set_prefix(head);
+                first.decor_mut().set_prefix(&prefix[end + 2..]);
+            }
+        }
+        let mut tables = ArrayOfTables::new();
+        for step in &self.steps {
+            let new_table = toml_edit::ser::to_document(step)
+                .map_err(|e| Error::ManifestDecode(e.to_string()))?
+                .as_table()
+                .clone();
+            let old = old_tables
+                .iter()
+                .position(|table| table.get("file").and_then(Item::as_str) == Some(&step.file));
+            let mut table = match old {
+                Some(index) => {
+                    let mut table = old_tables.remove(index);
+                    update_table(&mut table, new_table);
+                    table
+                }
+                None => new_table,
+            };
+            // written in the order of the steps, not where they were, with a
+            // blank line between them
+            table.set_position(None);
+            let prefix = decor_prefix(table.decor()).trim_start_matches('\n');
+            let prefix = if tables.is_empty() {
+                prefix.to_string()
+            } else {
+                format!("\n{}", prefix)
+            };
+            table.decor_mut().set_prefix(prefix);
+            tables.push(table);
+        }
+        document.insert("step", Item::ArrayOfTables(tables));
+
+        let content = document.to_string();
+        let mut file = NamedTempFile::new_in(opt_dir_path)?;
+        file.write_all(content.as_bytes())?;
+        file.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// A file name for a new step that no listed step uses yet.
+    pub fn new_file_name(&self, opt_dir_path: &str) -> (String, String) {
+        let mut n = self.steps.len() + 1;
+        loop {
+            let name = format!("step-{}", n);
+            let file = format!("{}.py", name);
+            let taken = self.steps.iter().any(|s| s.name == name || s.file == file)
+                || Path::new(opt_dir_path).join(&file).exists();
+            if !taken {
+                return (name, file);
+            }
+            n += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_save_manifest() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        assert_eq!(Manifest::load(opt_dir_path).unwrap(), None);
+
+        fs::write(
+            temp_dir.path().join(MANIFEST_FILE_NAME),
+            r#"
+[[step]]
+name = "detect-start"
+description = "Remember the first line"
+file = "opt-1.py"
+
+[[step]]
+name = "lowercase"
+file = "lowercase.py"
+enabled = false
+timeout = 5
+memory_mb = 64
+"#,
+        )
+        .unwrap();
+        let manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        assert_eq!(manifest.steps.len(), 2);
+        assert_eq!(manifest.steps[0].name, "detect-start");
+        assert_eq!(manifest.steps[0].description, "Remember the first line");
+        assert!(manifest.steps[0].enabled);
+        assert_eq!(manifest.steps[1].file, "lowercase.py");
+        assert!(!manifest.steps[1].enabled);
+        assert!(manifest.steps[0].limits.is_empty());
+        assert_eq!(manifest.steps[1].limits.timeout, Some(5.0));
+        assert_eq!(manifest.steps[1].limits.memory_mb, Some(64));
+
+        manifest.save(opt_dir_path).unwrap();
+        assert_eq!(Manifest::load(opt_dir_path).unwrap().unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_save_manifest_in_place() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let path = temp_dir.path().join(MANIFEST_FILE_NAME);
+        fs::write(
+            &path,
+            r#"# the pipeline of the release notes
+
+# keeps the header
+[[step]]
+name = 'header'   # shown in --trace
+file = "header.py"
+timeout = 5
+
+[[step]]
+name = "lowercase"
+file = "lowercase.py"
+enabled = false
+"#,
+        )
+        .unwrap();
+        let mut manifest = Manifest::load(opt_dir_path).unwrap().unwrap();
+        manifest.steps.swap(0, 1);
+        manifest.steps[0].enabled = true;
+        manifest.steps.push(ManifestStep {
+            name: "trim".to_string(),
+            description: String::new(),
+            enabled: true,
+            file: "trim.py".to_string(),
+            limits: Limits::default(),
+        });
+        manifest.save(opt_dir_path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            r#"# the pipeline of the release notes
+
+[[step]]
+name = "lowercase"
+file = "lowercase.py"
+
+# keeps the header
+[[step]]
+name = 'header'   # shown in --trace
+file = "header.py"
+timeout = 5
+
+[[step]]
+name = "trim"
+file = "trim.py"
+"#
+        );
+        assert_eq!(Manifest::load(opt_dir_path).unwrap().unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_load_invalid_manifest() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(
+            temp_dir.path().join(MANIFEST_FILE_NAME),
+            "[[step]]\nname = 1\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Manifest::load(opt_dir_path),
+            Err(Error::ManifestDecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_manifest_file_outside() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir = temp_dir.path().join("opt");
+        fs::create_dir(&opt_dir).unwrap();
+        let opt_dir_path = opt_dir.to_str().unwrap();
+        fs::write(temp_dir.path().join("outside.py"), "").unwrap();
+        let mut files = vec!["../outside.py", "/etc/passwd", "", "sub/../../outside.py"];
+        #[cfg(unix)]
+        {
+            let link = opt_dir.join("link.py");
+            std::os::unix::fs::symlink(temp_dir.path().join("outside.py"), link).unwrap();
+            files.push("link.py");
+        }
+
+        for file in files {
+            fs::write(
+                opt_dir.join(MANIFEST_FILE_NAME),
+                format!("[[step]]\nname = \"a\"\nfile = {:?}\n", file),
+            )
+            .unwrap();
+            match Manifest::load(opt_dir_path) {
+                Err(Error::ManifestDecode(message)) => {
+                    assert_eq!(
+                        message,
+                        format!("step file {} is outside the opt dir", file)
+                    )
+                }
+                r => panic!("Expected ManifestDecode for {:?}, got {:?}", file, r),
+            }
+        }
+
+        fs::write(
+            opt_dir.join(MANIFEST_FILE_NAME),
+            "[[step]]\nname = \"a\"\nfile = \"./sub/a.py\"\n",
+        )
+        .unwrap();
+        assert!(Manifest::load(opt_dir_path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_new_file_name() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("step-2.py"), "").unwrap();
+        let manifest = Manifest {
+            steps: vec![ManifestStep {
+                name: "first".to_string(),
+                description: String::new(),
+                enabled: true,
+                file: "opt-1.py".to_string(),
+                limits: Limits::default(),
+            }],
+        };
+        assert_eq!(
+            manifest.new_file_name(opt_dir_path),
+            ("step-3".to_string(), "step-3.py".to_string())
+        );
+    }
+}
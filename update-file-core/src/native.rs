@@ -0,0 +1,346 @@
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::core::{OperationData, OperationOutput};
+
+/// Extension of operation files run natively instead of by Python.
+pub const NATIVE_EXTENSION: &str = "toml";
+
+/// What a native operation works on.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApplyTo {
+    /// The content not consumed yet. It is changed, appended to the new
+    /// content and consumed.
+    #[default]
+    Remaining,
+    /// The new content built so far, changed in place.
+    Output,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum NativeSpec {
+    RegexReplace {
+        pattern: String,
+        replacement: String,
+        #[serde(default)]
+        limit: usize,
+    },
+    Replace {
+        pattern: String,
+        replacement: String,
+        #[serde(default)]
+        limit: usize,
+    },
+    InsertLines {
+        line: usize,
+        text: String,
+    },
+    DeleteLines {
+        line: usize,
+        #[serde(default = "default_count")]
+        count: usize,
+    },
+    SetData {
+        pattern: String,
+        key: String,
+        value: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct NativeFile {
+    #[serde(flatten)]
+    spec: NativeSpec,
+    #[serde(default)]
+    apply_to: ApplyTo,
+}
+
+#[derive(Debug)]
+enum Action {
+    RegexReplace {
+        regex: Regex,
+        replacement: String,
+        limit: usize,
+    },
+    Replace {
+        pattern: String,
+        replacement: String,
+        limit: usize,
+    },
+    InsertLines {
+        line: usize,
+        text: String,
+    },
+    DeleteLines {
+        line: usize,
+        count: usize,
+    },
+    SetData {
+        regex: Regex,
+        key: String,
+        value: String,
+    },
+}
+
+/// An operation implemented in Rust, declared in an `opt-<id>.toml` file:
+///
+/// ```toml
+/// kind = "regex-replace"
+/// pattern = 'version = "(\d+)"'
+/// replacement = 'version = "${1}1"'
+/// ```
+///
+/// The kinds are `regex-replace` and `replace` (`pattern`, `replacement` and
+/// an optional `limit` of replacements), `insert-lines` (`text` inserted
+/// before `line`), `delete-lines` (`count` lines from `line`) and `set-data`
+/// (sets `key` of the data map to `value`, `$1` by default, expanded from the
+/// first match of `pattern`). Lines count from 1. `apply_to = "output"` makes
+/// an operation work on the new content instead of the remaining content.
+#[derive(Debug)]
+pub struct NativeOperation {
+    action: Action,
+    apply_to: ApplyTo,
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+fn check_line(line: usize) -> Result<usize, String> {
+    if line == 0 {
+        Err("lines count from 1".to_string())
+    } else {
+        Ok(line)
+    }
+}
+
+fn insert_lines(content: &str, line: usize, text: &str) -> String {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let index = (line - 1).min(lines.len());
+    let mut result = lines[..index].concat();
+    // the inserted text always starts and ends a line
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(text);
+    if !text.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(&lines[index..].concat());
+    result
+}
+
+fn delete_lines(content: &str, line: usize, count: usize) -> String {
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .filter(|(i, _)| *i + 1 < line || *i + 1 >= line + count)
+        .map(|(_, l)| l)
+        .collect()
+}
+
+impl NativeOperation {
+    pub fn parse(content: &str) -> Result<NativeOperation, String> {
+        let file: NativeFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let action = match file.spec {
+            NativeSpec::RegexReplace {
+                pattern,
+                replacement,
+                limit,
+            } => Action::RegexReplace {
+                regex: compile(&pattern)?,
+                replacement,
+                limit,
+            },
+            NativeSpec::Replace {
+                pattern,
+                replacement,
+                limit,
+            } => Action::Replace {
+                pattern,
+                replacement,
+                limit,
+            },
+            NativeSpec::InsertLines { line, text } => Action::InsertLines {
+                line: check_line(line)?,
+                text,
+            },
+            NativeSpec::DeleteLines { line, count } => Action::DeleteLines {
+                line: check_line(line)?,
+                count,
+            },
+            NativeSpec::SetData {
+                pattern,
+                key,
+                value,
+            } => {
+                let regex = compile(&pattern)?;
+                // without a group, take the whole match
+                let value = value.unwrap_or_else(|| {
+                    if regex.captures_len() > 1 { "$1" } else { "$0" }.to_string()
+                });
+                Action::SetData { regex, key, value }
+            }
+        };
+        Ok(NativeOperation {
+            action,
+            apply_to: file.apply_to,
+        })
+    }
+
+    fn transform(&self, content: &str) -> String {
+        match &self.action {
+            Action::RegexReplace {
+                regex,
+                replacement,
+                limit,
+            } => regex
+                .replacen(content, *limit, replacement.as_str())
+                .into_owned(),
+            Action::Replace {
+                pattern,
+                replacement,
+                limit,
+            } if *limit > 0 => content.replacen(pattern.as_str(), replacement, *limit),
+            Action::Replace {
+                pattern,
+                replacement,
+                ..
+            } => content.replace(pattern.as_str(), replacement),
+            Action::InsertLines { line, text } => insert_lines(content, *line, text),
+            Action::DeleteLines { line, count } => delete_lines(content, *line, *count),
+            Action::SetData { .. } => content.to_string(),
+        }
+    }
+
+    /// Run the operation like a Python one would, reporting failures through
+    /// `error_message`.
+    pub fn run(&self, data: &OperationData) -> OperationOutput {
+        let mut output = OperationOutput::from_data(data);
+        let remaining = match data.full_content.get(data.content_index..) {
+            Some(remaining) => remaining,
+            None => {
+                output.error_message =
+                    format!("Content index {} is out of range", data.content_index);
+                return output;
+            }
+        };
+        if let Action::SetData { regex, key, value } = &self.action {
+            let content = match self.apply_to {
+                ApplyTo::Remaining => remaining,
+                ApplyTo::Output => data.new_content,
+            };
+            match regex.captures(content) {
+                Some(captures) => {
+                    let mut expanded = String::new();
+                    captures.expand(value, &mut expanded);
+                    output.data_map.insert(key.clone(), expanded);
+                }
+                None => output.error_message = format!("Pattern {} does not match", regex.as_str()),
+            }
+            return output;
+        }
+        match self.apply_to {
+            ApplyTo::Remaining => {
+                output.new_content.push_str(&self.transform(remaining));
+                output.content_index = data.full_content.len();
+            }
+            ApplyTo::Output => output.new_content = self.transform(data.new_content),
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn run(
+        opt: &str,
+        full_content: &str,
+        content_index: usize,
+        new_content: &str,
+    ) -> OperationOutput {
+        let data_map = HashMap::new();
+        NativeOperation::parse(opt).unwrap().run(&OperationData {
+            data_map: &data_map,
+            full_content,
+            content_index,
+            new_content,
+        })
+    }
+
+    #[test]
+    fn test_replace() {
+        let opt = "kind = 'regex-replace'\npattern = '(\\d+)'\nreplacement = '<$1>'";
+        let output = run(opt, "a1 b22 c3", 0, "");
+        assert_eq!(output.new_content, "a<1> b<22> c<3>");
+        assert_eq!(output.content_index, 9);
+
+        // only the remaining content is changed
+        let opt = "kind = 'regex-replace'\npattern = '\\d'\nreplacement = 'x'\nlimit = 1";
+        let output = run(opt, "a1 b2 c3", 3, "A1 ");
+        assert_eq!(output.new_content, "A1 bx c3");
+
+        let opt = "kind = 'replace'\npattern = '.'\nreplacement = '!'\napply_to = 'output'";
+        let output = run(opt, "a.b.c", 2, "a.");
+        assert_eq!(output.new_content, "a!");
+        assert_eq!(output.content_index, 2);
+    }
+
+    #[test]
+    fn test_insert_lines() {
+        assert_eq!(insert_lines("a\nb\n", 1, "x"), "x\na\nb\n");
+        assert_eq!(insert_lines("a\nb\n", 2, "x\ny\n"), "a\nx\ny\nb\n");
+        assert_eq!(insert_lines("a\nb\n", 3, "x"), "a\nb\nx\n");
+        assert_eq!(insert_lines("a\nb", 9, "x"), "a\nb\nx\n");
+        assert_eq!(insert_lines("", 1, "x"), "x\n");
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        assert_eq!(delete_lines("a\nb\nc\n", 2, 1), "a\nc\n");
+        assert_eq!(delete_lines("a\nb\nc", 2, 5), "a\n");
+        assert_eq!(delete_lines("a\nb\nc\n", 4, 1), "a\nb\nc\n");
+
+        let opt = "kind = 'delete-lines'\nline = 1";
+        let output = run(opt, "a\nb\nc\n", 2, "");
+        assert_eq!(output.new_content, "c\n");
+    }
+
+    #[test]
+    fn test_set_data() {
+        let opt = "kind = 'set-data'\npattern = 'version = \"(.*)\"'\nkey = 'version'";
+        let output = run(opt, "name = \"x\"\nversion = \"1.2\"\n", 0, "");
+        assert_eq!(output.data_map["version"], "1.2");
+        // nothing is consumed
+        assert_eq!(output.content_index, 0);
+        assert_eq!(output.new_content, "");
+
+        let opt = "kind = 'set-data'\npattern = '(?<major>\\d+)\\.\\d+'\nkey = 'major'\nvalue = 'v${major}'\napply_to = 'output'";
+        let output = run(opt, "", 0, "1.2");
+        assert_eq!(output.data_map["major"], "v1");
+
+        let opt = "kind = 'set-data'\npattern = 'missing'\nkey = 'k'";
+        let output = run(opt, "content", 0, "");
+        assert_eq!(output.error_message, "Pattern missing does not match");
+        assert!(!output.data_map.contains_key("k"));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(NativeOperation::parse("kind = 'unknown'").is_err());
+        assert!(
+            NativeOperation::parse("kind = 'regex-replace'\npattern = '('\nreplacement = ''")
+                .is_err()
+        );
+        assert!(NativeOperation::parse("kind = 'delete-lines'\nline = 0").is_err());
+    }
+}
@@ -0,0 +1,721 @@
+use crate::error::Error;
+use crate::limits::ProcessLimits;
+use crate::sandbox::{spawn, temp_file, Sandbox};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+use tempfile::NamedTempFile;
+use update_file_macros::get_content_const;
+use update_file_utils::get_content;
+
+const INIT_COLLECTED_DATA_STR: &str = get_content_const!(
+    "./runner.py",
+    "## Init collected data start",
+    "## Init collected data end"
+);
+const OPERATION_TEMPLE_STR: &str = get_content_const!(
+    "./runner.py",
+    "## Operation template start",
+    "## Operation template end"
+);
+
+static WRITE_CODE_BELOW: &str =
+    "## Write your code below, modify the code above, and DO NOT remove this line";
+
+pub fn get_operation_temple_python(user_opt_content: Option<&str>) -> String {
+    let opt_content = user_opt_content.unwrap_or(OPERATION_TEMPLE_STR);
+    format!(
+        "{}\n{}\n{}",
+        INIT_COLLECTED_DATA_STR, WRITE_CODE_BELOW, opt_content
+    )
+}
+
+pub fn get_operation_python(content: &str) -> Result<&str, String> {
+    get_content(content, format!("{}\n", WRITE_CODE_BELOW).as_str(), None)
+}
+
+/// Name the generated script gets in tracebacks, instead of its temporary path.
+const SCRIPT_NAME: &str = "<pipeline>";
+
+struct ScriptSegment {
+    id: usize,
+    file_name: String,
+    start_line: usize,
+    line_count: usize,
+}
+
+/// The code of several operations joined together, remembering which lines
+/// belong to which operation file.
+#[derive(Default)]
+pub struct PythonScript {
+    code: String,
+    segments: Vec<ScriptSegment>,
+}
+
+impl PythonScript {
+    pub fn push_operation(&mut self, id: usize, file_name: &str, code: &str) {
+        // tell the runner which operation runs, for the limits of each one
+        self.code.push_str(&format!("report_progress({})\n", id));
+        let start_line = self.code.matches('\n').count() + 1;
+        self.code.push_str(code);
+        self.code.push('\n');
+        self.segments.push(ScriptSegment {
+            id,
+            file_name: file_name.to_string(),
+            start_line,
+            line_count: code.lines().count(),
+        });
+        // remember which operation reported an error first, and record the
+        // output of the operation if the run is traced
+        self.code.push_str(&format!(
+            "if error_message and error_step is None:\n    error_step = {}\ntrace_step()\n",
+            id
+        ));
+    }
+
+    fn locate(&self, line: usize) -> Option<(&ScriptSegment, usize)> {
+        self.segments
+            .iter()
+            .find(|s| line >= s.start_line && line < s.start_line + s.line_count)
+            .map(|s| (s, line - s.start_line + 1))
+    }
+
+    /// Point the traceback of a failed run at the operation files instead of
+    /// the generated script.
+    fn attribute_error(&self, error: Error, line_offset: usize) -> Error {
+        let (stderr, exit_code) = match error {
+            Error::InterpreterFailed { stderr, exit_code } => (stderr, exit_code),
+            e => return e,
+        };
+        let pattern = format!("File \"{}\", line ", SCRIPT_NAME);
+        let mut location = None;
+        let mut lines = Vec::new();
+        for line in stderr.lines() {
+            let mapped = line.find(&pattern).and_then(|start| {
+                let number_start = start + pattern.len();
+                let number_end = line[number_start..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(line.len(), |i| number_start + i);
+                let script_line = line[number_start..number_end].parse::<usize>().ok()?;
+                let (segment, opt_line) = self.locate(script_line.checked_sub(line_offset)?)?;
+                location = Some((segment, opt_line));
+                Some(format!(
+                    "{}File \"{}\", line {}{}",
+                    &line[..start],
+                    segment.file_name,
+                    opt_line,
+                    &line[number_end..]
+                ))
+            });
+            lines.push(mapped.unwrap_or_else(|| line.to_string()));
+        }
+        let stderr = lines.join("\n");
+        match location {
+            Some((segment, line)) => Error::OperationFailed {
+                id: segment.id,
+                file: segment.file_name.clone(),
+                line,
+                message: stderr
+                    .lines()
+                    .rev()
+                    .find(|l| !l.trim().is_empty())
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+                stderr,
+            },
+            None => Error::InterpreterFailed { stderr, exit_code },
+        }
+    }
+}
+
+/// Drop the `## <name> start` to `## <name> end` section of the runner script.
+fn remove_section(content: &str, name: &str) -> String {
+    let start = format!("## {} start", name);
+    let end = format!("## {} end\n", name);
+    match (content.find(&start), content.find(&end)) {
+        (Some(start_index), Some(end_index)) => format!(
+            "{}{}",
+            &content[..start_index],
+            &content[end_index + end.len()..]
+        ),
+        _ => content.to_string(),
+    }
+}
+
+/// Returns the runner script and the number of lines before the operation code.
+fn create_operation_runner_python(opt_content: &str) -> (String, usize) {
+    insert_operation_code(include_str!("./runner.py"), opt_content)
+}
+
+/// Like [`create_operation_runner_python`], but without reading the input and
+/// writing the output, which the worker does for every run itself.
+fn create_operation_worker_python(opt_content: &str) -> (String, usize) {
+    let runner_content = remove_section(
+        &remove_section(include_str!("./runner.py"), "Read input"),
+        "Write output",
+    );
+    insert_operation_code(&runner_content, opt_content)
+}
+
+fn insert_operation_code(runner_content: &str, opt_content: &str) -> (String, usize) {
+    let line_offset = runner_content
+        .find(OPERATION_TEMPLE_STR)
+        .map_or(0, |pos| runner_content[..pos].matches('\n').count() + 1);
+    let content = runner_content.replace(
+        OPERATION_TEMPLE_STR,
+        format!("\n{}\n", opt_content).as_str(),
+    );
+    (content, line_offset)
+}
+
+fn python_command(python_runner: &str) -> Command {
+    let python_args = python_runner.split_whitespace().collect::<Vec<&str>>();
+    let python = python_args.first().unwrap_or(&"python3");
+    let python_args = python_args.get(1..).unwrap_or(&[]);
+
+    let mut command = Command::new(python);
+    command.args(python_args);
+    command
+}
+
+fn run_python_code(
+    code: &str,
+    args: &Vec<&str>,
+    envs: &HashMap<&str, &str>,
+    python_runner: &str,
+    std_pass: bool,
+    limits: &ProcessLimits,
+    sandbox: Option<&Sandbox>,
+) -> Result<String, Error> {
+    // in the scratch dir, as a sandbox hides the temporary dir
+    let mut file = temp_file(sandbox)?;
+    file.write_all(code.as_bytes())?;
+
+    let mut command = python_command(python_runner);
+    if let Some(sandbox) = sandbox {
+        sandbox.apply(&mut command);
+    }
+    command.arg(file.path()).args(args).envs(envs);
+    if std_pass {
+        // what the operations print goes to stderr, so it never mixes with an
+        // output written to stdout; stderr is still captured, so a failure can
+        // be reported with its traceback
+        command.stdout(io::stderr()).stdin(Stdio::inherit());
+    } else {
+        command.stdout(Stdio::piped()).stdin(Stdio::null());
+    }
+    command.stderr(Stdio::piped());
+    limits.apply(&mut command);
+    let output = limits.wait(spawn(&mut command, sandbox)?)?;
+
+    let stderr =
+        String::from_utf8_lossy(&output.stderr).replace(file.path().to_str().unwrap(), SCRIPT_NAME);
+    if output.status.success() {
+        if std_pass {
+            io::stderr().write_all(stderr.as_bytes())?;
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::InterpreterFailed {
+            stderr,
+            exit_code: output.status.code(),
+        })
+    }
+}
+
+/// Run the script on `data`, both as JSON. If `trace` is given, the output
+/// after each operation is appended to it, one JSON line each.
+pub fn run_operation_python(
+    script: &PythonScript,
+    data: &str,
+    python_runner: &str,
+    limits: &ProcessLimits,
+    sandbox: Option<&Sandbox>,
+    trace: Option<&Path>,
+) -> Result<String, Error> {
+    let (content, line_offset) = create_operation_runner_python(&script.code);
+    let mut input = temp_file(sandbox)?;
+    input.write_all(data.as_bytes())?;
+    let output = temp_file(sandbox)?;
+    let mut envs: HashMap<&str, &str> = [
+        ("INPUT_FILE", input.path().to_str().unwrap()),
+        ("OUTPUT_FILE", output.path().to_str().unwrap()),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    if let Some(trace) = trace {
+        envs.insert("TRACE_FILE", trace.to_str().unwrap());
+    }
+    run_python_code(
+        &content,
+        &vec![],
+        &envs,
+        python_runner,
+        true,
+        limits,
+        sandbox,
+    )
+    .map_err(|e| script.attribute_error(e, line_offset))?;
+    Ok(std::fs::read_to_string(output.path())?)
+}
+
+/// Name of the worker script in tracebacks, instead of its temporary path.
+const WORKER_NAME: &str = "<worker>";
+
+/// Answer of the worker to one input.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WorkerReply {
+    Output(serde_json::Value),
+    Error(String),
+}
+
+/// A Python interpreter that loads a script once and then runs it for many
+/// inputs, so the interpreter does not start again for every run. Every run
+/// starts from fresh globals, but all runs share the sandbox of the worker.
+/// What the operations print goes to stderr.
+pub struct PythonWorker {
+    script: PythonScript,
+    line_offset: usize,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    file: NamedTempFile,
+    // kept until the interpreter stopped, like the script file
+    _sandbox: Option<Sandbox>,
+}
+
+impl PythonWorker {
+    pub fn start(
+        script: PythonScript,
+        python_runner: &str,
+        sandbox: Option<Sandbox>,
+    ) -> Result<PythonWorker, Error> {
+        let mut file = temp_file(sandbox.as_ref())?;
+        file.write_all(include_str!("./worker.py").as_bytes())?;
+
+        let mut command = python_command(python_runner);
+        if let Some(sandbox) = &sandbox {
+            sandbox.apply(&mut command);
+        }
+        command
+            .arg(file.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        let mut child = spawn(&mut command, sandbox.as_ref())?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        let (code, line_offset) = create_operation_worker_python(&script.code);
+        writeln!(stdin, "{}", serde_json::to_string(&code)?)?;
+        Ok(PythonWorker {
+            script,
+            line_offset,
+            child,
+            stdin: Some(stdin),
+            stdout,
+            file,
+            _sandbox: sandbox,
+        })
+    }
+
+    /// Run the script for one `OperationData` and return the `OperationOutput`,
+    /// both as JSON.
+    pub fn run(&mut self, data: &str) -> Result<String, Error> {
+        let stdin = self.stdin.as_mut().unwrap();
+        // the input must fit on one line, which JSON without pretty printing does
+        let sent = writeln!(stdin, "{}", data.trim_end()).and_then(|_| stdin.flush());
+        let mut line = String::new();
+        if sent.is_err() || self.stdout.read_line(&mut line)? == 0 {
+            // the interpreter is gone, report how it ended
+            self.stdin = None;
+            let status = self.child.wait()?;
+            return Err(Error::InterpreterFailed {
+                stderr: String::new(),
+                exit_code: status.code(),
+            });
+        }
+        match serde_json::from_str(&line)? {
+            WorkerReply::Output(output) => Ok(output.to_string()),
+            WorkerReply::Error(traceback) => {
+                let stderr = traceback.replace(self.file.path().to_str().unwrap(), WORKER_NAME);
+                let error = Error::InterpreterFailed {
+                    stderr,
+                    exit_code: None,
+                };
+                match self.script.attribute_error(error, self.line_offset) {
+                    Error::InterpreterFailed { stderr, .. } => {
+                        Err(Error::WorkerRunFailed { stderr })
+                    }
+                    e => Err(e),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PythonWorker {
+    fn drop(&mut self) {
+        // closing stdin ends the worker loop
+        self.stdin = None;
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{OperationData, OperationOutput};
+
+    #[test]
+    fn test_get_operation_python() {
+        let user_content = "print('Hello')";
+        let content = format!(
+            "{}\n{}\n{}\n{}",
+            INIT_COLLECTED_DATA_STR, user_content, WRITE_CODE_BELOW, OPERATION_TEMPLE_STR
+        );
+        let operation = get_operation_python(&content);
+        assert_eq!(operation.unwrap(), OPERATION_TEMPLE_STR);
+        let content = format!(
+            "{}\n{}\n{}",
+            INIT_COLLECTED_DATA_STR, WRITE_CODE_BELOW, user_content
+        );
+        let operation = get_operation_python(&content);
+        assert_eq!(operation.unwrap(), user_content);
+    }
+
+    #[test]
+    fn test_run_python_code() {
+        let code = "print('Hello!')";
+        let output = run_python_code(
+            code,
+            &vec![],
+            &HashMap::new(),
+            "python3",
+            true,
+            &ProcessLimits::default(),
+            None,
+        );
+        assert_eq!(output.unwrap(), "");
+        let code = r#"
+from sys import argv
+print(f"Hello! {argv[1]}")
+"#;
+        let output = run_python_code(
+            code,
+            &vec!["World"],
+            &HashMap::new(),
+            "python3",
+            false,
+            &ProcessLimits::default(),
+            None,
+        );
+        assert_eq!(output.unwrap(), "Hello! World\n");
+        let code = r#"
+from os import environ
+print(f"Hello! {environ['TEST']}")
+"#;
+        let envs = [("TEST", "World")].iter().cloned().collect();
+        let output = run_python_code(
+            code,
+            &vec![],
+            &envs,
+            "python3",
+            false,
+            &ProcessLimits::default(),
+            None,
+        );
+        assert_eq!(output.unwrap(), "Hello! World\n");
+    }
+
+    #[test]
+    fn test_python_code_with_error() {
+        let erroneous_python_code = "print(Hello World)";
+        let args: Vec<&str> = Vec::new();
+        let result = run_python_code(
+            erroneous_python_code,
+            &args,
+            &HashMap::new(),
+            "python3",
+            false,
+            &ProcessLimits::default(),
+            None,
+        );
+
+        assert!(result.is_err(), "Expected an error, but got Ok");
+
+        if let Err(e) = result {
+            match e {
+                Error::InterpreterFailed { stderr, exit_code } => {
+                    assert!(stderr.contains("SyntaxError"));
+                    assert_eq!(exit_code, Some(1));
+                }
+                e => panic!("Expected InterpreterFailed, got {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_create_operation_runner_python() {
+        let class_content = OPERATION_TEMPLE_STR.to_owned() + "\nprint('Hello!')";
+        let (content, line_offset) = create_operation_runner_python(&class_content);
+        assert!(content.contains("print('Hello!')"));
+        let first_line = content.lines().nth(line_offset).unwrap();
+        assert_eq!(first_line, OPERATION_TEMPLE_STR.lines().next().unwrap());
+    }
+
+    #[test]
+    fn test_run_operation_python_large_input() {
+        // far over the limit of a single argument
+        let full_content = "0123456789abcde\n".repeat(256 * 1024);
+        let mut script = PythonScript::default();
+        script.push_operation(
+            1,
+            "opt-1.py",
+            "new_content += content.upper()\ncontent_index = len(content)",
+        );
+        let data_map = HashMap::new();
+        let data = OperationData {
+            data_map: &data_map,
+            full_content: &full_content,
+            content_index: 0,
+            new_content: "",
+        };
+        let data_str = serde_json::to_string(&data).unwrap();
+        let output_str = run_operation_python(
+            &script,
+            &data_str,
+            "python3",
+            &ProcessLimits::default(),
+            None,
+            None,
+        );
+        let output = serde_json::from_str::<OperationOutput>(&output_str.unwrap()).unwrap();
+        assert_eq!(output.content_index, full_content.len());
+        assert_eq!(output.new_content, full_content.to_uppercase());
+    }
+
+    #[test]
+    fn test_run_operation_python() {
+        let mut script = PythonScript::default();
+        script.push_operation(2, "opt-2.py", include_str!("./tests/opt-2.py"));
+        let data_map = [("start".to_string(), "Hello".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        let data = OperationData {
+            data_map: &data_map,
+            full_content: "Hello!\nWorld!",
+            content_index: 0,
+            new_content: "",
+        };
+        let data_str = serde_json::to_string(&data).unwrap();
+        let output_str = run_operation_python(
+            &script,
+            &data_str,
+            "python3",
+            &ProcessLimits::default(),
+            None,
+            None,
+        );
+        println!("OUTPUT: {:?}", output_str);
+        let output = serde_json::from_str::<OperationOutput>(&output_str.unwrap()).unwrap();
+        let expected = OperationOutput {
+            data_map: [
+                ("start".to_string(), "Hello".to_string()),
+                ("end".to_string(), "World!".to_string()),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            new_content: "hello!\nworld!\n".to_string(),
+            content_index: data.full_content.len() - 1,
+            error_message: "".to_string(),
+            error_step: None,
+        };
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_run_operation_python_check_fail() {
+        let mut script = PythonScript::default();
+        script.push_operation(2, "opt-2.py", include_str!("./tests/opt-2.py"));
+        let data_map = [("start".to_string(), "hello".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        let data = OperationData {
+            data_map: &data_map,
+            full_content: "Hello!\nWorld!",
+            content_index: 0,
+            new_content: "",
+        };
+        let data_str = serde_json::to_string(&data).unwrap();
+        let output_str = run_operation_python(
+            &script,
+            &data_str,
+            "python3",
+            &ProcessLimits::default(),
+            None,
+            None,
+        );
+        println!("OUTPUT: {:?}", output_str);
+        let output = serde_json::from_str::<OperationOutput>(&output_str.unwrap()).unwrap();
+        let expected = OperationOutput {
+            data_map: [("start".to_string(), "hello".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            new_content: "".to_string(),
+            content_index: 0,
+            error_message: "Operation check starting condition failed".to_string(),
+            error_step: Some(2),
+        };
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_create_operation_worker_python() {
+        let (content, line_offset) = create_operation_worker_python("print('Hello!')");
+        assert!(content.contains("print('Hello!')"));
+        assert!(content.contains("## Collect data start"));
+        assert!(!content.contains("## Read input start"));
+        assert!(!content.contains("## Write output start"));
+        assert_eq!(content.lines().nth(line_offset).unwrap(), "print('Hello!')");
+    }
+
+    #[test]
+    fn test_python_worker() {
+        let mut script = PythonScript::default();
+        script.push_operation(1, "opt-1.py", include_str!("./tests/opt-1.py"));
+        script.push_operation(
+            2,
+            "opt-2.py",
+            "print('to stderr')\nnew_content += content[content_index:]\ncontent_index = len(content)\ncount = data_map.get('count', '')\ndata_map['count'] = count + '1'\nif content == 'fail':\n    raise ValueError(content)",
+        );
+        let mut worker = PythonWorker::start(script, "python3", None).unwrap();
+        // raised outside of the operations
+        match worker.run(r#"{"data_map": {}}"#) {
+            Err(Error::WorkerRunFailed { stderr }) => assert!(stderr.contains("full_content")),
+            r => panic!("Expected WorkerRunFailed, got {:?}", r),
+        }
+        let data_map = HashMap::new();
+        for full_content in ["Hello", "World", "fail", "Again"] {
+            let data = OperationData {
+                data_map: &data_map,
+                full_content,
+                content_index: 0,
+                new_content: "",
+            };
+            let result = worker.run(&serde_json::to_string(&data).unwrap());
+            if full_content == "fail" {
+                match result {
+                    Err(Error::OperationFailed {
+                        id, line, message, ..
+                    }) => {
+                        assert_eq!(id, 2);
+                        assert_eq!(line, 7);
+                        assert_eq!(message, "ValueError: fail");
+                    }
+                    r => panic!("Expected OperationFailed, got {:?}", r),
+                }
+                continue;
+            }
+            let output = serde_json::from_str::<OperationOutput>(&result.unwrap()).unwrap();
+            assert_eq!(output.new_content, full_content);
+            assert_eq!(output.data_map["start"], full_content);
+            // nothing is left over from the inputs before
+            assert_eq!(output.data_map["count"], "1");
+        }
+    }
+
+    #[test]
+    fn test_python_worker_fd_1() {
+        let mut script = PythonScript::default();
+        script.push_operation(
+            1,
+            "opt-1.py",
+            "import os, subprocess, sys\nos.write(1, b'to fd 1\\n')\nsubprocess.run(['echo', 'from a child'])\nsys.__stdout__.write('to sys.__stdout__\\n')\nnew_content = os.read(0, 10).decode() + content",
+        );
+        let mut worker = PythonWorker::start(script, "python3", None).unwrap();
+        let data_map = HashMap::new();
+        for full_content in ["Hello", "World"] {
+            let data = OperationData {
+                data_map: &data_map,
+                full_content,
+                content_index: 0,
+                new_content: "",
+            };
+            let output = worker.run(&serde_json::to_string(&data).unwrap()).unwrap();
+            let output = serde_json::from_str::<OperationOutput>(&output).unwrap();
+            assert_eq!(output.new_content, full_content);
+        }
+    }
+
+    #[test]
+    fn test_python_worker_exit() {
+        let mut script = PythonScript::default();
+        script.push_operation(1, "opt-1.py", "import os\nos._exit(3)");
+        let mut worker = PythonWorker::start(script, "python3", None).unwrap();
+        let data_map = HashMap::new();
+        let data = OperationData {
+            data_map: &data_map,
+            full_content: "Hello",
+            content_index: 0,
+            new_content: "",
+        };
+        match worker.run(&serde_json::to_string(&data).unwrap()) {
+            Err(Error::InterpreterFailed { exit_code, .. }) => assert_eq!(exit_code, Some(3)),
+            r => panic!("Expected InterpreterFailed, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_run_operation_python_error_location() {
+        let mut script = PythonScript::default();
+        script.push_operation(1, "opt-1.py", include_str!("./tests/opt-1.py"));
+        script.push_operation(
+            2,
+            "opt-2.py",
+            "\nstart = data_map['start']\nmissing = data_map['missing']",
+        );
+        let data_map = HashMap::new();
+        let data = OperationData {
+            data_map: &data_map,
+            full_content: "Hello!\nWorld!",
+            content_index: 0,
+            new_content: "",
+        };
+        let data_str = serde_json::to_string(&data).unwrap();
+        let result = run_operation_python(
+            &script,
+            &data_str,
+            "python3",
+            &ProcessLimits::default(),
+            None,
+            None,
+        );
+        match result {
+            Err(Error::OperationFailed {
+                id,
+                file,
+                line,
+                message,
+                stderr,
+            }) => {
+                assert_eq!(id, 2);
+                assert_eq!(file, "opt-2.py");
+                assert_eq!(line, 3);
+                assert_eq!(message, "KeyError: 'missing'");
+                assert!(stderr.contains("File \"opt-2.py\", line 3"));
+            }
+            r => panic!("Expected OperationFailed, got {:?}", r),
+        }
+    }
+}
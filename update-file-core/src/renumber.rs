@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+use crate::error::Error;
+
+/// Suffix of an operation file while it is being renamed.
+const STAGED_SUFFIX: &str = ".renaming";
+
+/// The plan being applied in an opt dir, until all its renames are done.
+const JOURNAL_FILE_NAME: &str = ".renaming.json";
+
+/// Temporary name of a file on its way from `from` to `to`. It does not look
+/// like an operation file.
+fn staged_name(from: &str, to: &str) -> String {
+    format!("{}~{}{}", from, to, STAGED_SUFFIX)
+}
+
+/// What an interrupted [`RenamePlan`] is recovered from: all its renames, and
+/// whether every file got its temporary name, after which the plan is
+/// finished instead of undone.
+#[derive(Deserialize, Serialize)]
+struct Journal {
+    staged: bool,
+    renames: Vec<(String, String)>,
+}
+
+impl Journal {
+    fn write(&self, dir: &Path) -> Result<(), Error> {
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string(self)?.as_bytes())?;
+        file.as_file().sync_all()?;
+        file.persist(dir.join(JOURNAL_FILE_NAME))
+            .map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    fn read(dir: &Path) -> Result<Option<Journal>, Error> {
+        match fs::read_to_string(dir.join(JOURNAL_FILE_NAME)) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove(dir: &Path) -> Result<(), Error> {
+        Ok(fs::remove_file(dir.join(JOURNAL_FILE_NAME))?)
+    }
+}
+
+/// Hold the opt dir for renames until dropped, so two processes never rename
+/// or recover in it at the same time.
+fn lock(dir: &Path) -> io::Result<File> {
+    let file = File::open(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: the descriptor is open for the whole call
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(file)
+}
+
+/// Renames of operation files in an opt dir. They are applied in two phases,
+/// first every file to a temporary name and then to its new name, so no two
+/// files ever claim the same id, whatever order the renames come in. If a
+/// rename fails, the ones already done are rolled back and the opt dir is left
+/// as it was. The whole plan is journaled first, so a plan interrupted by a
+/// crash is finished or undone by [`recover_renames`].
+#[derive(Default)]
+pub struct RenamePlan {
+    renames: Vec<(String, String)>,
+}
+
+impl RenamePlan {
+    pub fn push(&mut self, from: &str, to: &str) {
+        if from != to {
+            self.renames.push((from.to_string(), to.to_string()));
+        }
+    }
+
+    pub fn apply(&self, opt_dir_path: &str) -> Result<(), Error> {
+        self.apply_with(opt_dir_path, |from, to| fs::rename(from, to))
+    }
+
+    fn apply_with<F>(&self, opt_dir_path: &str, mut rename: F) -> Result<(), Error>
+    where
+        F: FnMut(&Path, &Path) -> io::Result<()>,
+    {
+        let dir = Path::new(opt_dir_path);
+        let _lock = lock(dir)?;
+        recover(dir)?;
+        if self.renames.is_empty() {
+            return Ok(());
+        }
+        let staged: Vec<String> = self
+            .renames
+            .iter()
+            .map(|(from, to)| staged_name(from, to))
+            .collect();
+        let mut journal = Journal {
+            staged: false,
+            renames: self.renames.clone(),
+        };
+        journal.write(dir)?;
+
+        for (i, (from, _)) in self.renames.iter().enumerate() {
+            if let Err(e) = rename(&dir.join(from), &dir.join(&staged[i])) {
+                // best effort, anything left staged is undone by recover_renames
+                let undone = self.renames[..i]
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .all(|(j, (from, _))| rename(&dir.join(&staged[j]), &dir.join(from)).is_ok());
+                if undone {
+                    Journal::remove(dir)?;
+                }
+                return Err(e.into());
+            }
+        }
+        journal.staged = true;
+        journal.write(dir)?;
+        for (i, (_, to)) in self.renames.iter().enumerate() {
+            if let Err(e) = rename(&dir.join(&staged[i]), &dir.join(to)) {
+                let restaged = self.renames[..i]
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .all(|(j, (_, to))| rename(&dir.join(to), &dir.join(&staged[j])).is_ok());
+                // until all files are staged again, recovery finishes the plan
+                if restaged {
+                    journal.staged = false;
+                    journal.write(dir)?;
+                    let undone = self.renames.iter().enumerate().rev().all(|(j, (from, _))| {
+                        rename(&dir.join(&staged[j]), &dir.join(from)).is_ok()
+                    });
+                    if undone {
+                        Journal::remove(dir)?;
+                    }
+                }
+                return Err(e.into());
+            }
+        }
+        Journal::remove(dir)
+    }
+}
+
+/// Finish or undo the journaled plan of `dir`, the lock held.
+fn recover(dir: &Path) -> Result<(), Error> {
+    let journal = match Journal::read(dir)? {
+        Some(journal) => journal,
+        None => return Ok(()),
+    };
+    for (from, to) in &journal.renames {
+        let staged = dir.join(staged_name(from, to));
+        if staged.exists() {
+            let target = if journal.staged { to } else { from };
+            fs::rename(staged, dir.join(target))?;
+        }
+    }
+    Journal::remove(dir)
+}
+
+/// Finish or undo a [`RenamePlan`] interrupted in `opt_dir_path`: a plan that
+/// staged all its files is finished, any other is undone, so the operations
+/// are either all renumbered or not at all. Safe to run from several
+/// processes at once.
+pub fn recover_renames(opt_dir_path: &str) -> Result<(), Error> {
+    let dir = Path::new(opt_dir_path);
+    let _lock = lock(dir)?;
+    recover(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_apply_rename_plan() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        fs::write(temp_dir.path().join("opt-1.py"), "1").unwrap();
+        fs::write(temp_dir.path().join("opt-2.py"), "2").unwrap();
+        fs::write(temp_dir.path().join("opt-3.py"), "3").unwrap();
+
+        // rotate the files, every target is taken at the start
+        let mut plan = RenamePlan::default();
+        plan.push("opt-1.py", "opt-2.py");
+        plan.push("opt-2.py", "opt-3.py");
+        plan.push("opt-3.py", "opt-1.py");
+        plan.apply(opt_dir_path).unwrap();
+
+        assert_eq!(read(temp_dir.path(), "opt-1.py"), "3");
+        assert_eq!(read(temp_dir.path(), "opt-2.py"), "1");
+        assert_eq!(read(temp_dir.path(), "opt-3.py"), "2");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_apply_rename_plan_rollback() {
+        let mut plan = RenamePlan::default();
+        plan.push("opt-3.py", "opt-4.py");
+        plan.push("opt-2.py", "opt-3.py");
+        plan.push("opt-1.py", "opt-2.py");
+
+        // fail at every single rename of both phases
+        for fail_at in 0..6 {
+            let temp_dir = tempdir().unwrap();
+            let opt_dir_path = temp_dir.path().to_str().unwrap();
+            for id in 1..=3 {
+                fs::write(
+                    temp_dir.path().join(format!("opt-{}.py", id)),
+                    id.to_string(),
+                )
+                .unwrap();
+            }
+
+            let mut count = 0;
+            let result = plan.apply_with(opt_dir_path, |from, to| {
+                count += 1;
+                if count == fail_at + 1 {
+                    return Err(io::Error::other("rename failed"));
+                }
+                fs::rename(from, to)
+            });
+            assert!(matches!(result, Err(Error::Io(_))));
+
+            // the opt dir is unchanged
+            assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+            for id in 1..=3 {
+                assert_eq!(
+                    read(temp_dir.path(), &format!("opt-{}.py", id)),
+                    id.to_string()
+                );
+            }
+        }
+    }
+
+    /// The opt dir of `plan` interrupted after its first `done` renames.
+    fn interrupt(plan: &RenamePlan, done: usize) -> tempfile::TempDir {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        for id in 1..=3 {
+            fs::write(
+                temp_dir.path().join(format!("opt-{}.py", id)),
+                id.to_string(),
+            )
+            .unwrap();
+        }
+        let mut count = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            plan.apply_with(opt_dir_path, |from, to| {
+                if count == done {
+                    panic!("interrupted");
+                }
+                count += 1;
+                fs::rename(from, to)
+            })
+        }));
+        assert!(result.is_err());
+        temp_dir
+    }
+
+    #[test]
+    fn test_recover_renames() {
+        let mut plan = RenamePlan::default();
+        plan.push("opt-3.py", "opt-4.py");
+        plan.push("opt-2.py", "opt-3.py");
+        plan.push("opt-1.py", "opt-2.py");
+
+        // interrupted in the first phase, e.g. with only opt-3.py staged
+        for done in 0..3 {
+            let temp_dir = interrupt(&plan, done);
+            let dir = temp_dir.path();
+            recover_renames(dir.to_str().unwrap()).unwrap();
+            for id in 1..=3 {
+                assert_eq!(read(dir, &format!("opt-{}.py", id)), id.to_string());
+            }
+            assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+        }
+
+        // interrupted in the second phase
+        for done in 3..6 {
+            let temp_dir = interrupt(&plan, done);
+            let dir = temp_dir.path();
+            recover_renames(dir.to_str().unwrap()).unwrap();
+            for id in 2..=4 {
+                assert_eq!(read(dir, &format!("opt-{}.py", id)), (id - 1).to_string());
+            }
+            assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+        }
+
+        // a later plan recovers first
+        let temp_dir = interrupt(&plan, 1);
+        let dir = temp_dir.path();
+        let mut swap = RenamePlan::default();
+        swap.push("opt-1.py", "opt-2.py");
+        swap.push("opt-2.py", "opt-1.py");
+        swap.apply(dir.to_str().unwrap()).unwrap();
+        assert_eq!(read(dir, "opt-1.py"), "2");
+        assert_eq!(read(dir, "opt-2.py"), "1");
+        assert_eq!(read(dir, "opt-3.py"), "3");
+    }
+
+    #[test]
+    fn test_recover_renames_concurrently() {
+        let mut plan = RenamePlan::default();
+        plan.push("opt-3.py", "opt-4.py");
+        plan.push("opt-2.py", "opt-3.py");
+        plan.push("opt-1.py", "opt-2.py");
+        let temp_dir = interrupt(&plan, 4);
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| recover_renames(opt_dir_path).unwrap());
+            }
+        });
+        for id in 2..=4 {
+            assert_eq!(
+                read(temp_dir.path(), &format!("opt-{}.py", id)),
+                (id - 1).to_string()
+            );
+        }
+    }
+}
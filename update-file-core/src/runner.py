@@ -0,0 +1,181 @@
+#!/usr/bin/env python3
+
+
+## Data class start
+from dataclasses import dataclass
+from typing import Optional, Union
+
+
+@dataclass
+class OperationData:
+    data_map: dict[str, str]
+    full_content: Union[str, bytes]
+    content_index: int
+    new_content: Union[str, bytes] = ""
+
+
+@dataclass
+class OperationOutput:
+    data_map: dict[str, str]
+    content_index: int
+    new_content: str
+    error_message: str
+    error_step: Optional[int] = None
+
+
+## Data class end
+
+## Read input start
+from json import load
+from os import environ
+
+# The input is passed as a file, as it may be too large for an argument
+with open(environ["INPUT_FILE"], encoding="utf-8") as f:
+    json_in = load(f)
+## Read input end
+
+## Init data start
+# In byte mode the contents are base64, the operations work on bytes
+binary = json_in.pop("binary", False)
+if binary:
+    from base64 import b64decode
+
+    json_in["full_content"] = b64decode(json_in["full_content"])
+    json_in["new_content"] = b64decode(json_in.get("new_content", ""))
+data = OperationData(**json_in)
+## Init data end
+
+## Init runner state start
+# The first operation that set error_message, tracked by the runner after each operation
+error_step = None
+
+from json import loads
+from os import environ
+
+# The runner reports which operation runs here, and the CPU time used before
+# it, to apply the limits of each one
+progress_file = environ.get("PROGRESS_FILE")
+# The CPU time and memory limits of each operation, set as it starts, with the
+# most the operations from it on may use as the hard limits
+step_limits = loads(environ.get("STEP_LIMITS", "{}"))
+
+
+def set_step_limit(resource, kind, soft, hard):
+    # the hard limit can only be lowered, and the soft one never goes above it
+    current = resource.getrlimit(kind)[1]
+    if hard is None or (current != resource.RLIM_INFINITY and hard > current):
+        hard = current
+    if soft is None or (hard != resource.RLIM_INFINITY and soft > hard):
+        soft = hard
+    resource.setrlimit(kind, (soft, hard))
+
+
+def report_progress(step):
+    if progress_file:
+        cpu_time = 0.0
+        if step_limits:
+            import resource
+            from math import ceil
+
+            usage = resource.getrusage(resource.RUSAGE_SELF)
+            cpu_time = usage.ru_utime + usage.ru_stime
+            limits = step_limits.get(str(step), {})
+
+            def rlimit(value):
+                # beyond what the system can take, there is no limit
+                return None if value is None or value >= 2**63 else value
+
+            def seconds(key):
+                value = limits.get(key)
+                return rlimit(None if value is None else ceil(cpu_time + value))
+
+            def size(key):
+                value = limits.get(key)
+                return rlimit(None if value is None else value * 1024 * 1024)
+
+            set_step_limit(
+                resource,
+                resource.RLIMIT_CPU,
+                seconds("cpu_time"),
+                seconds("cpu_time_rest"),
+            )
+            set_step_limit(
+                resource,
+                resource.RLIMIT_AS,
+                size("memory_mb"),
+                size("memory_mb_rest"),
+            )
+        with open(progress_file, "w") as f:
+            f.write(f"{step} {cpu_time!r}")
+
+
+# When tracing, the runner appends the output after each operation here, one JSON line each
+trace_file = environ.get("TRACE_FILE")
+
+
+def trace_step():
+    if trace_file:
+        from base64 import b64encode
+        from json import dumps
+
+        snapshot = {
+            "data_map": data_map,
+            "content_index": content_index,
+            "new_content": b64encode(new_content).decode() if binary else new_content,
+            "error_message": error_message,
+            "error_step": error_step,
+        }
+        with open(trace_file, "a", encoding="utf-8") as f:
+            f.write(dumps(snapshot) + "\n")
+
+## Init runner state end
+
+## Init collected data start
+# You can use this to store data to help you keep track of the state
+# The runner won't touch this data also don't care and will forget it after the execution
+data_map: dict[str, str] = data.data_map
+
+# The content you will be working with
+# content_index counts characters (code points) of it, like Python strings do,
+# or bytes in byte mode, where content is bytes
+content = data.full_content
+content_index = data.content_index
+
+# The content of file you will get in the end, built on by every operation
+new_content = data.new_content
+
+# If you encounter an error, set this variable to a message that describes the error
+# And the runner will print it out and stop the execution
+error_message = ""
+
+## Init collected data end
+
+## Operation template start
+
+raise NotImplementedError("Please implement the operation block")
+
+## Operation template end
+
+## Collect data start
+from base64 import b64encode
+from dataclasses import asdict
+
+output = asdict(
+    OperationOutput(
+        data_map=data_map,
+        content_index=content_index,
+        new_content=b64encode(new_content).decode() if binary else new_content,
+        error_message=error_message,
+        error_step=error_step,
+    )
+)
+## Collect data end
+
+## Write output start
+from json import dumps
+from os import environ
+
+output_path = environ.get("OUTPUT_FILE", "/dev/stdout")
+with open(output_path, "w", encoding="utf-8") as f:
+    f.write(dumps(output))
+## Write output end
@@ -0,0 +1,329 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+use crate::core::{decode_output, encode_data, Operation, OperationData, OperationOutput};
+use crate::error::Error;
+use crate::limits::{Limits, ProcessLimits};
+use crate::native::{NativeOperation, NATIVE_EXTENSION};
+use crate::python::{run_operation_python, PythonScript};
+use crate::sandbox::{spawn, temp_file, Sandbox};
+
+/// Settings of a run that runtimes may need.
+pub struct RunContext<'a> {
+    /// Command running Python, with its arguments.
+    pub python_runner: &'a str,
+    /// Limits of the operations without their own.
+    pub limits: Limits,
+    /// Whether operations run as processes are isolated from the system.
+    pub sandboxed: bool,
+    /// Whether the content stands for bytes, see
+    /// [`crate::OperationManager::set_binary`].
+    pub binary: bool,
+}
+
+impl RunContext<'_> {
+    /// A new sandbox for a process, if the run is sandboxed.
+    pub(crate) fn sandbox(&self) -> Result<Option<Sandbox>, Error> {
+        self.sandboxed.then(Sandbox::new).transpose()
+    }
+}
+
+/// Runs the operations whose files have a given extension.
+///
+/// A runtime gets consecutive operations of its extension at once and returns
+/// their combined output; the output of one runtime is the input of the next.
+pub trait Runtime: Send + Sync {
+    /// Extension of the operation files, without the dot.
+    fn extension(&self) -> &str;
+
+    fn run(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+    ) -> Result<OperationOutput, Error>;
+
+    /// Like [`Runtime::run`], also handing the output after each operation to
+    /// `step`, for [`crate::OperationManager::trace_operations`]. By default
+    /// the operations run one at a time, which is only the same as `run` if
+    /// they share nothing but their output; runtimes where they do share more
+    /// should record the outputs while running them together.
+    fn run_traced(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+        step: &mut dyn FnMut(&Operation, &OperationOutput),
+    ) -> Result<OperationOutput, Error> {
+        run_each(opts, data, |opt, data| {
+            let output = self.run(std::slice::from_ref(opt), data, context)?;
+            step(opt, &output);
+            Ok(output)
+        })
+    }
+}
+
+/// Join the code of the operations into one script, also returning the id of
+/// the last one.
+pub(crate) fn create_script(opts: &[Operation]) -> Result<(PythonScript, Option<usize>), Error> {
+    let mut script = PythonScript::default();
+    let mut last_id = None;
+    for opt in opts {
+        script.push_operation(opt.id(), opt.file_name(), &opt.content()?);
+        last_id = Some(opt.id());
+    }
+    Ok((script, last_id))
+}
+
+/// Runs `.py` operations. Consecutive ones are joined into one script, so they
+/// share their globals.
+pub struct PythonRuntime;
+
+impl Runtime for PythonRuntime {
+    fn extension(&self) -> &str {
+        "py"
+    }
+
+    fn run(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+    ) -> Result<OperationOutput, Error> {
+        let (script, last_id) = create_script(opts)?;
+        let data_str = encode_data(data, context.binary)?;
+        let sandbox = context.sandbox()?;
+        let limits = ProcessLimits::new(opts, context.limits, sandbox.as_ref())?;
+        let output_str = run_operation_python(
+            &script,
+            &data_str,
+            context.python_runner,
+            &limits,
+            sandbox.as_ref(),
+            None,
+        )?;
+        decode_output(&output_str, data.full_content, context.binary, last_id)
+    }
+
+    fn run_traced(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+        step: &mut dyn FnMut(&Operation, &OperationOutput),
+    ) -> Result<OperationOutput, Error> {
+        let (script, last_id) = create_script(opts)?;
+        let data_str = encode_data(data, context.binary)?;
+        let sandbox = context.sandbox()?;
+        let limits = ProcessLimits::new(opts, context.limits, sandbox.as_ref())?;
+        let trace = temp_file(sandbox.as_ref())?;
+        let result = run_operation_python(
+            &script,
+            &data_str,
+            context.python_runner,
+            &limits,
+            sandbox.as_ref(),
+            Some(trace.path()),
+        );
+        // the steps before a failed one are still recorded
+        let snapshots = std::fs::read_to_string(trace.path())?;
+        for (opt, snapshot) in opts.iter().zip(snapshots.lines()) {
+            step(
+                opt,
+                &decode_output(snapshot, data.full_content, context.binary, None)?,
+            );
+        }
+        decode_output(&result?, data.full_content, context.binary, last_id)
+    }
+}
+
+/// Run the operations one by one, handing the output of each to the next.
+/// Stops at the first one that reports an error.
+pub(crate) fn run_each<F>(
+    opts: &[Operation],
+    data: &OperationData,
+    mut run: F,
+) -> Result<OperationOutput, Error>
+where
+    F: FnMut(&Operation, &OperationData) -> Result<OperationOutput, Error>,
+{
+    let mut output = OperationOutput::from_data(data);
+    for opt in opts {
+        output = run(opt, &output.next_data(data.full_content))?;
+        if !output.error_message.is_empty() {
+            output.error_step.get_or_insert(opt.id());
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// Runs [`NativeOperation`]s from `.toml` files, without any interpreter.
+pub struct NativeRuntime;
+
+impl NativeRuntime {
+    fn load(opt: &Operation) -> Result<NativeOperation, Error> {
+        NativeOperation::parse(&opt.content()?).map_err(|message| Error::InvalidOperation {
+            id: opt.id(),
+            file: opt.file_name().to_string(),
+            message,
+        })
+    }
+}
+
+impl Runtime for NativeRuntime {
+    fn extension(&self) -> &str {
+        NATIVE_EXTENSION
+    }
+
+    fn run(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        _context: &RunContext,
+    ) -> Result<OperationOutput, Error> {
+        run_each(opts, data, |opt, data| Ok(Self::load(opt)?.run(data)))
+    }
+}
+
+/// Run an operation as its own process: the `OperationData` JSON is written to
+/// its stdin, and it writes the `OperationOutput` JSON to the file named by the
+/// `OUTPUT_FILE` environment variable. What it prints goes to stderr.
+///
+/// `program` is given the operation file, or a copy of it in the scratch dir
+/// when sandboxed, as the sandbox may hide the operation dir.
+fn run_command(
+    program: &str,
+    opt: &Operation,
+    data: &OperationData,
+    context: &RunContext,
+) -> Result<OperationOutput, Error> {
+    let sandbox = context.sandbox()?;
+    let mut command = Command::new(program);
+    let _script = match &sandbox {
+        Some(sandbox) => {
+            sandbox.apply(&mut command);
+            let mut script = temp_file(Some(sandbox))?;
+            script.write_all(&std::fs::read(opt.path())?)?;
+            command.arg(script.path());
+            Some(script)
+        }
+        None => {
+            command.arg(opt.path());
+            None
+        }
+    };
+    let output_file = temp_file(sandbox.as_ref())?;
+    let limits = ProcessLimits::new(std::slice::from_ref(opt), context.limits, sandbox.as_ref())?;
+    limits.apply(&mut command);
+    command
+        .env("OUTPUT_FILE", output_file.path())
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .stderr(Stdio::piped());
+    let mut child = spawn(&mut command, sandbox.as_ref())?;
+
+    // written aside, so a full stderr pipe cannot block the input
+    let mut stdin = child.stdin.take().unwrap();
+    let data_str = encode_data(data, context.binary)?;
+    let writer = thread::spawn(move || match stdin.write_all(data_str.as_bytes()) {
+        // the operation does not have to read its input
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    });
+    let output = limits.wait(child)?;
+    writer.join().unwrap()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !output.status.success() {
+        return Err(Error::CommandFailed {
+            id: opt.id(),
+            file: opt.file_name().to_string(),
+            stderr,
+            exit_code: output.status.code(),
+        });
+    }
+    io::stderr().write_all(stderr.as_bytes())?;
+    let output_str = std::fs::read_to_string(output_file.path())?;
+    decode_output(
+        &output_str,
+        data.full_content,
+        context.binary,
+        Some(opt.id()),
+    )
+}
+
+/// Runs `.sh` operations with `sh`, each as its own process speaking the JSON
+/// protocol through stdin and `$OUTPUT_FILE`, e.g. with `jq`:
+///
+/// ```sh
+/// jq '{data_map, content_index: (.full_content | length),
+///      new_content: (.new_content + (.full_content | ascii_upcase)),
+///      error_message: ""}' > "$OUTPUT_FILE"
+/// ```
+pub struct ShellRuntime;
+
+impl Runtime for ShellRuntime {
+    fn extension(&self) -> &str {
+        "sh"
+    }
+
+    fn run(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+    ) -> Result<OperationOutput, Error> {
+        run_each(opts, data, |opt, data| {
+            run_command("sh", opt, data, context)
+        })
+    }
+}
+
+/// The runtimes known to an [`crate::OperationManager`].
+#[derive(Clone)]
+pub(crate) struct Runtimes {
+    runtimes: Vec<Arc<dyn Runtime>>,
+}
+
+impl Default for Runtimes {
+    fn default() -> Runtimes {
+        Runtimes {
+            runtimes: vec![
+                Arc::new(PythonRuntime),
+                Arc::new(NativeRuntime),
+                Arc::new(ShellRuntime),
+                #[cfg(feature = "rhai")]
+                Arc::new(crate::script::RhaiRuntime),
+                #[cfg(feature = "wasm")]
+                Arc::new(crate::wasm::WasmRuntime),
+            ],
+        }
+    }
+}
+
+impl Runtimes {
+    /// Add a runtime, replacing the one with the same extension.
+    pub fn register(&mut self, runtime: Arc<dyn Runtime>) {
+        self.runtimes
+            .retain(|known| known.extension() != runtime.extension());
+        self.runtimes.push(runtime);
+    }
+
+    pub fn extensions(&self) -> impl Iterator<Item = &str> {
+        self.runtimes.iter().map(|runtime| runtime.extension())
+    }
+
+    pub fn get(&self, opt: &Operation) -> Result<&Arc<dyn Runtime>, Error> {
+        self.runtimes
+            .iter()
+            .find(|runtime| runtime.extension() == opt.extension())
+            .ok_or_else(|| Error::InvalidOperation {
+                id: opt.id(),
+                file: opt.file_name().to_string(),
+                message: format!("No runtime for .{} files", opt.extension()),
+            })
+    }
+}
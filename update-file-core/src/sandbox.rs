@@ -0,0 +1,361 @@
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::{Child, Command};
+use tempfile::{NamedTempFile, TempDir};
+
+use crate::error::Error;
+
+/// Isolation of an operation process, on Linux only. The process gets its own
+/// user, mount, network and PID namespaces: it has no network, sees no other
+/// process, and sees the whole filesystem read-only except a scratch dir, with
+/// `/tmp`, `/run`, `/var/run` and `/dev/shm` emptied so the sockets there are
+/// out of reach. Its environment is cleared but for `PATH`, with `HOME` and
+/// `TMPDIR` set to the scratch dir, which is also its working dir if the
+/// current one is hidden. The files the runner exchanges with the
+/// process are kept in the scratch dir too.
+///
+/// Unprivileged user namespaces must be enabled, and the kernel must support
+/// `mount_setattr` (Linux 5.12).
+pub(crate) struct Sandbox {
+    scratch: TempDir,
+}
+
+/// A temporary file the process can write, also in a sandbox.
+pub(crate) fn temp_file(sandbox: Option<&Sandbox>) -> io::Result<NamedTempFile> {
+    match sandbox {
+        Some(sandbox) => NamedTempFile::new_in(sandbox.scratch()),
+        None => NamedTempFile::new(),
+    }
+}
+
+/// Start `command`, telling a failure to set up the sandbox from a missing
+/// program.
+pub(crate) fn spawn(command: &mut Command, sandbox: Option<&Sandbox>) -> Result<Child, Error> {
+    command.spawn().map_err(|e| match sandbox {
+        Some(_) if e.kind() != io::ErrorKind::NotFound => Error::SandboxFailed(e),
+        _ => Error::Io(e),
+    })
+}
+
+impl Sandbox {
+    pub fn new() -> Result<Sandbox, Error> {
+        if cfg!(not(target_os = "linux")) {
+            return Err(Error::SandboxFailed(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sandboxes are only supported on Linux",
+            )));
+        }
+        Ok(Sandbox {
+            scratch: TempDir::new()?,
+        })
+    }
+
+    /// The only directory the process can write.
+    pub fn scratch(&self) -> &Path {
+        self.scratch.path()
+    }
+
+    /// Set up `command` to run in the sandbox. Call it before setting the
+    /// environment of the command, which it clears.
+    pub fn apply(&self, command: &mut Command) {
+        command.env_clear();
+        if let Some(path) = env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        command
+            .env("HOME", self.scratch())
+            .env("TMPDIR", self.scratch());
+        #[cfg(target_os = "linux")]
+        linux::isolate(command, self.scratch());
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+    use std::path::Path;
+    use std::process::Command;
+    use std::ptr;
+
+    const MOUNT_ATTR_RDONLY: u64 = 0x1;
+    const OPEN_TREE_CLONE: libc::c_uint = 0x1;
+    const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+    /// `struct mount_attr` of `mount_setattr(2)`.
+    #[repr(C)]
+    struct MountAttr {
+        attr_set: u64,
+        attr_clr: u64,
+        propagation: u64,
+        userns_fd: u64,
+    }
+
+    fn check(result: libc::c_long) -> io::Result<()> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+        // SAFETY: plain system calls on valid buffers
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            check(fd as libc::c_long)?;
+            let written = libc::write(fd, content.as_ptr().cast(), content.len());
+            libc::close(fd);
+            check(written as libc::c_long)
+        }
+    }
+
+    fn set_read_only(path: &CStr, read_only: bool) -> io::Result<()> {
+        let attr = MountAttr {
+            attr_set: if read_only { MOUNT_ATTR_RDONLY } else { 0 },
+            attr_clr: if read_only { 0 } else { MOUNT_ATTR_RDONLY },
+            propagation: 0,
+            userns_fd: 0,
+        };
+        // SAFETY: the path and the attributes outlive the call
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                libc::AT_RECURSIVE as libc::c_uint,
+                &attr as *const MountAttr,
+                std::mem::size_of::<MountAttr>(),
+            )
+        })
+    }
+
+    /// Cover `path` with an empty tmpfs, if it exists.
+    fn hide(path: &CStr) -> io::Result<()> {
+        // SAFETY: the path outlives the call
+        match check(unsafe {
+            libc::mount(
+                c"tmpfs".as_ptr(),
+                path.as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                ptr::null(),
+            )
+        } as libc::c_long)
+        {
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Turn the calling process into the parent of the rest of the command,
+    /// which goes on as the init process of the PID namespace unshared before.
+    /// The parent only waits for it and then ends the same way.
+    ///
+    /// # Safety
+    ///
+    /// Must be called in the child of `fork`, before the `exec`.
+    unsafe fn enter_pid_namespace() -> io::Result<()> {
+        let pid = libc::fork();
+        check(pid as libc::c_long)?;
+        if pid == 0 {
+            // not outlive the parent, which is the process that gets killed
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) as libc::c_long)?;
+            return Ok(());
+        }
+        // hold nothing of the command open, e.g. its pipes must close with it
+        libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0);
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) < 0 {
+            if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                libc::_exit(127);
+            }
+        }
+        if libc::WIFSIGNALED(status) {
+            let signal = libc::WTERMSIG(status);
+            let no_core = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+            libc::signal(signal, libc::SIG_DFL);
+            libc::kill(libc::getpid(), signal);
+        }
+        libc::_exit(libc::WEXITSTATUS(status));
+    }
+
+    pub fn isolate(command: &mut Command, scratch: &Path) {
+        // everything is prepared here, the child must not allocate
+        // SAFETY: getuid and getgid cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let uid_map = format!("{} {} 1", uid, uid);
+        let gid_map = format!("{} {} 1", gid, gid);
+        // the scratch dir may be in a hidden dir, so it is created again there
+        let dirs: Vec<CString> = scratch
+            .ancestors()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .map(|dir| CString::new(dir.as_os_str().as_bytes()).unwrap())
+            .collect();
+        let scratch = CString::new(scratch.as_os_str().as_bytes()).unwrap();
+        let cwd = match command.get_current_dir() {
+            Some(dir) => Some(dir.to_path_buf()),
+            None => std::env::current_dir().ok(),
+        }
+        .map(|dir| CString::new(dir.as_os_str().as_bytes()).unwrap());
+        // SAFETY: only async-signal-safe system calls are made in the child
+        unsafe {
+            command.pre_exec(move || {
+                check(libc::unshare(
+                    libc::CLONE_NEWUSER
+                        | libc::CLONE_NEWNS
+                        | libc::CLONE_NEWNET
+                        | libc::CLONE_NEWPID,
+                ) as libc::c_long)?;
+                write_file(c"/proc/self/setgroups", b"deny")?;
+                write_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+                write_file(c"/proc/self/gid_map", gid_map.as_bytes())?;
+                // keep the mounts below from reaching the rest of the system
+                check(libc::mount(
+                    ptr::null(),
+                    c"/".as_ptr(),
+                    ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    ptr::null(),
+                ) as libc::c_long)?;
+                // a detached copy of the scratch dir, put back once the dirs
+                // holding sockets and shared memory are hidden
+                let tree = libc::syscall(
+                    libc::SYS_open_tree,
+                    libc::AT_FDCWD,
+                    scratch.as_ptr(),
+                    OPEN_TREE_CLONE
+                        | libc::O_CLOEXEC as libc::c_uint
+                        | libc::AT_RECURSIVE as libc::c_uint,
+                );
+                check(tree)?;
+                for path in [c"/tmp", c"/run", c"/var/run", c"/dev/shm"] {
+                    hide(path)?;
+                }
+                for dir in &dirs {
+                    libc::mkdir(dir.as_ptr(), 0o700);
+                }
+                check(libc::syscall(
+                    libc::SYS_move_mount,
+                    tree as libc::c_int,
+                    c"".as_ptr(),
+                    libc::AT_FDCWD,
+                    scratch.as_ptr(),
+                    MOVE_MOUNT_F_EMPTY_PATH,
+                ))?;
+                libc::close(tree as libc::c_int);
+                set_read_only(c"/", true)?;
+                set_read_only(&scratch, false)?;
+                // the working dir again by its path, or the scratch dir if hidden
+                if cwd.as_ref().is_none_or(|cwd| libc::chdir(cwd.as_ptr()) < 0) {
+                    check(libc::chdir(scratch.as_ptr()) as libc::c_long)?;
+                }
+                enter_pid_namespace()?;
+                // the processes of this namespace only
+                check(libc::mount(
+                    c"proc".as_ptr(),
+                    c"/proc".as_ptr(),
+                    c"proc".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    ptr::null(),
+                ) as libc::c_long)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationManager;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use tempfile::tempdir;
+
+    /// Whether the system lets this process create a sandbox.
+    fn is_supported() -> bool {
+        let Ok(sandbox) = Sandbox::new() else {
+            return false;
+        };
+        let mut command = Command::new("true");
+        sandbox.apply(&mut command);
+        spawn(&mut command, Some(&sandbox))
+            .and_then(|mut child| Ok(child.wait()?))
+            .is_ok_and(|status| status.success())
+    }
+
+    #[test]
+    fn test_sandbox() {
+        assert!(
+            is_supported(),
+            "sandboxes are not supported here, run with `--skip test_sandbox`"
+        );
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let outside = temp_dir.path().join("outside.txt");
+        let socket_path = temp_dir.path().join("agent.sock");
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        fs::write(
+            temp_dir.path().join("opt-1.py"),
+            format!(
+                r#"import os, socket, tempfile
+def attempt(key, action):
+    try:
+        action()
+        data_map[key] = "allowed"
+    except OSError:
+        data_map[key] = "denied"
+attempt("write", lambda: open({:?}, "w"))
+attempt("scratch", lambda: tempfile.TemporaryFile())
+attempt("network", lambda: socket.create_connection(("1.1.1.1", 53), timeout=1))
+attempt("socket", lambda: socket.socket(socket.AF_UNIX).connect({:?}))
+data_map["processes"] = str(sorted(int(p) for p in os.listdir("/proc") if p.isdigit()))
+data_map["env"] = str(sorted(k for k in os.environ if k.startswith("CARGO")))
+new_content += content
+content_index = len(content)"#,
+                outside.to_str().unwrap(),
+                socket_path.to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("opt-2.sh"), "touch \"$HOME/y\" && cat > /dev/null && printf '{\"data_map\": {}, \"content_index\": 5, \"new_content\": \"sh\", \"error_message\": \"\"}' > \"$OUTPUT_FILE\"").unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        manager.set_sandboxed(true);
+        let output = manager.run_operations(1, "Hello", "python3").unwrap();
+        assert_eq!(output.new_content, "Hello");
+        assert_eq!(output.data_map["write"], "denied");
+        assert_eq!(output.data_map["scratch"], "allowed");
+        assert_eq!(output.data_map["network"], "denied");
+        assert_eq!(output.data_map["socket"], "denied");
+        assert_eq!(output.data_map["processes"], "[1]");
+        assert_eq!(output.data_map["env"], "[]");
+        assert!(!outside.exists());
+
+        let output = manager.run_all_operations("Hello", "python3").unwrap();
+        assert_eq!(output.new_content, "sh");
+        let mut worker = manager.start_worker(1, "python3").unwrap();
+        let output = worker
+            .run_json(r#"{"data_map": {}, "full_content": "abc", "content_index": 0}"#)
+            .unwrap();
+        assert_eq!(output.data_map["write"], "denied");
+        assert_eq!(output.data_map["network"], "denied");
+        assert_eq!(output.data_map["socket"], "denied");
+
+        // without the sandbox the same operation can do all of it
+        manager.set_sandboxed(false);
+        let output = manager.run_operations(1, "Hello", "python3").unwrap();
+        assert_eq!(output.data_map["write"], "allowed");
+        assert_eq!(output.data_map["socket"], "allowed");
+        assert!(outside.exists());
+    }
+}
@@ -0,0 +1,344 @@
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Map, Position, Scope, AST, INT};
+use std::collections::HashMap;
+
+use crate::core::{byte_index, char_index, Operation, OperationData, OperationOutput};
+use crate::error::Error;
+use crate::limits::{Budget, Limit, Limits};
+use crate::runtime::{RunContext, Runtime};
+
+/// Extension of operation files run by the embedded Rhai engine.
+pub const RHAI_EXTENSION: &str = "rhai";
+
+/// Runs `.rhai` operations inside the process, without any interpreter. The
+/// operations see the same variables as Python ones: `data_map`, `content`,
+/// `content_index`, `new_content` and `error_message`.
+///
+/// ```rhai
+/// let line = content.sub_string(content_index).split("\n", 2)[0];
+/// new_content += line.to_upper();
+/// content_index += line.len();
+/// ```
+///
+/// Consecutive operations share their scope, like Python ones share their
+/// globals. What they print goes to stderr. The timeout and CPU time limit of
+/// an operation are checked as it runs; its memory limit caps the size of
+/// every string, array and map it builds.
+pub struct RhaiRuntime;
+
+/// How many Rhai operations run between two checks of the time limits.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Hold the next operation run by `engine` to `limits`.
+fn set_limits(engine: &mut Engine, limits: Limits) {
+    let budget = Budget::start(limits);
+    engine.on_progress(move |count| {
+        if count % CHECK_INTERVAL != 0 {
+            return None;
+        }
+        budget.exceeded().map(Dynamic::from)
+    });
+    let bytes = limits.memory_mb.map_or(0, |mb| {
+        usize::try_from(mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX)
+    });
+    // zero for no limit
+    engine.set_max_string_size(bytes);
+    engine.set_max_array_size(bytes / size_of::<Dynamic>());
+    engine.set_max_map_size(bytes / size_of::<Dynamic>());
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|text| eprintln!("{}", text));
+    engine.on_debug(|text, _, pos| eprintln!("{:?} {}", pos, text));
+    engine
+}
+
+fn create_scope(data: &OperationData) -> Scope<'static> {
+    let data_map: Map = data
+        .data_map
+        .iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect();
+    let mut scope = Scope::new();
+    scope.push("data_map", data_map);
+    scope.push("content", data.full_content.to_string());
+    scope.push(
+        "content_index",
+        char_index(data.full_content, data.content_index) as INT,
+    );
+    scope.push("new_content", data.new_content.to_string());
+    scope.push("error_message", String::new());
+    scope
+}
+
+/// The value of a variable of the protocol, which a script may have given a
+/// wrong type.
+fn get_value<T: Clone + 'static>(scope: &Scope, name: &str, expected: &str) -> Result<T, String> {
+    scope
+        .get_value::<T>(name)
+        .ok_or_else(|| format!("`{}` must be {}", name, expected))
+}
+
+fn collect_output(scope: &Scope, full_content: &str) -> Result<OperationOutput, String> {
+    let data_map = get_value::<Map>(scope, "data_map", "a map")?
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<HashMap<_, _>>();
+    let content_index = get_value::<INT>(scope, "content_index", "an integer")?;
+    Ok(OperationOutput {
+        data_map,
+        content_index: byte_index(
+            full_content,
+            usize::try_from(content_index)
+                .map_err(|_| format!("`content_index` {} is negative", content_index))?,
+        ),
+        new_content: get_value::<ImmutableString>(scope, "new_content", "a string")?.to_string(),
+        error_message: get_value::<ImmutableString>(scope, "error_message", "a string")?
+            .to_string(),
+        error_step: None,
+    })
+}
+
+fn script_error(opt: &Operation, position: Position, message: String) -> Error {
+    Error::OperationFailed {
+        id: opt.id(),
+        file: opt.file_name().to_string(),
+        line: position.line().unwrap_or_default(),
+        stderr: message.clone(),
+        message,
+    }
+}
+
+/// The error of an operation that failed while running, reporting a limit
+/// it ran into as [`Error::LimitExceeded`].
+fn run_error(opt: &Operation, limits: Limits, error: Box<EvalAltResult>) -> Error {
+    let limit = match &*error {
+        EvalAltResult::ErrorTerminated(token, _) => token.clone().try_cast::<Limit>(),
+        EvalAltResult::ErrorDataTooLarge(..) => limits.memory_mb.map(Limit::Memory),
+        _ => None,
+    };
+    match limit {
+        Some(limit) => Error::LimitExceeded {
+            id: opt.id(),
+            file: opt.file_name().to_string(),
+            limit,
+        },
+        None => script_error(opt, error.position(), error.to_string()),
+    }
+}
+
+impl Runtime for RhaiRuntime {
+    fn extension(&self) -> &str {
+        RHAI_EXTENSION
+    }
+
+    fn run(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+    ) -> Result<OperationOutput, Error> {
+        self.run_traced(opts, data, context, &mut |_, _| {})
+    }
+
+    fn run_traced(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+        step: &mut dyn FnMut(&Operation, &OperationOutput),
+    ) -> Result<OperationOutput, Error> {
+        let mut engine = engine();
+        // compile all operations first, a syntax error fails before any runs
+        let asts = opts
+            .iter()
+            .map(|opt| {
+                engine
+                    .compile(opt.content()?)
+                    .map_err(|e| script_error(opt, e.position(), e.to_string()))
+            })
+            .collect::<Result<Vec<AST>, Error>>()?;
+
+        let mut scope = create_scope(data);
+        let mut output = OperationOutput::from_data(data);
+        for (opt, ast) in opts.iter().zip(&asts) {
+            let limits = opt.limits().or(context.limits);
+            set_limits(&mut engine, limits);
+            engine
+                .run_ast_with_scope(&mut scope, ast)
+                .map_err(|e| run_error(opt, limits, e))?;
+            output = collect_output(&scope, data.full_content).map_err(|message| {
+                Error::InvalidOperation {
+                    id: opt.id(),
+                    file: opt.file_name().to_string(),
+                    message,
+                }
+            })?;
+            let failed = !output.error_message.is_empty();
+            if failed {
+                output.error_step = Some(opt.id());
+            }
+            step(opt, &output);
+            if failed {
+                break;
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationManager;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn write_opt(dir: &std::path::Path, id: usize, content: &str) {
+        fs::write(dir.join(format!("opt-{}.rhai", id)), content).unwrap();
+    }
+
+    #[test]
+    fn test_rhai_runtime() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        write_opt(
+            temp_dir.path(),
+            1,
+            "let line = content.sub_string(content_index).split(\"\\n\", 2)[0] + \"\\n\";\nnew_content += line.to_upper();\ncontent_index += line.len();\nprint(\"first line done\");",
+        );
+        // the scope is shared with the operations before
+        write_opt(
+            temp_dir.path(),
+            2,
+            "new_content += content.sub_string(content_index);\ncontent_index = content.len();\ndata_map.first = line;",
+        );
+        fs::write(
+            temp_dir.path().join("opt-3.py"),
+            "data_map['length'] = str(len(new_content))",
+        )
+        .unwrap();
+
+        let mut manager = OperationManager::new(opt_dir_path);
+        let output = manager.run_all_operations("abc\ndef\n", "python3").unwrap();
+        assert_eq!(output.new_content, "ABC\ndef\n");
+        assert_eq!(output.data_map["first"], "abc\n");
+        assert_eq!(output.data_map["length"], "8");
+
+        // indexes count code points, as `len` and `sub_string` do
+        let output = manager
+            .run_all_operations("\u{e9}\u{1f600}\r\nw\u{f6}rld\r\n", "python3")
+            .unwrap();
+        assert_eq!(output.new_content, "\u{c9}\u{1f600}\r\nw\u{f6}rld\r\n");
+        assert_eq!(output.data_map["first"], "\u{e9}\u{1f600}\r\n");
+        assert_eq!(output.data_map["length"], "11");
+
+        // a trace records each step of the shared scope
+        let trace = manager
+            .trace_operations(3, "abc\ndef\n", "python3")
+            .unwrap();
+        assert!(trace.failure.is_none());
+        assert_eq!(trace.steps[0].added_content, "ABC\n");
+        assert_eq!(trace.steps[1].added_content, "def\n");
+        assert_eq!(trace.steps[1].data_map_delta.changed["first"].new, "abc\n");
+    }
+
+    #[test]
+    fn test_rhai_runtime_errors() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        write_opt(temp_dir.path(), 1, "error_message = \"stop\";");
+        write_opt(temp_dir.path(), 2, "new_content = \"never\";");
+        match manager.run_all_operations("abc", "python3") {
+            Err(Error::OperationReported { id, message }) => {
+                assert_eq!(id, Some(1));
+                assert_eq!(message, "stop");
+            }
+            r => panic!("Expected OperationReported, got {:?}", r),
+        }
+
+        write_opt(
+            temp_dir.path(),
+            1,
+            "new_content += content;\n\nundefined_call();",
+        );
+        match manager.run_all_operations("abc", "python3") {
+            Err(Error::OperationFailed { id, file, line, .. }) => {
+                assert_eq!(id, 1);
+                assert_eq!(file, "opt-1.rhai");
+                assert_eq!(line, 3);
+            }
+            r => panic!("Expected OperationFailed, got {:?}", r),
+        }
+
+        write_opt(temp_dir.path(), 1, "content_index = \"all\";");
+        match manager.run_all_operations("abc", "python3") {
+            Err(e @ Error::InvalidOperation { .. }) => assert_eq!(
+                e.to_string(),
+                "Invalid operation 1 (opt-1.rhai): `content_index` must be an integer"
+            ),
+            r => panic!("Expected InvalidOperation, got {:?}", r),
+        }
+
+        // a syntax error in a later operation stops the run before it starts
+        write_opt(temp_dir.path(), 1, "new_content += content;");
+        write_opt(temp_dir.path(), 2, "let = 1;");
+        match manager.run_all_operations("abc", "python3") {
+            Err(Error::OperationFailed { id, line, .. }) => {
+                assert_eq!(id, 2);
+                assert_eq!(line, 1);
+            }
+            r => panic!("Expected OperationFailed, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_rhai_runtime_limits() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        write_opt(temp_dir.path(), 1, "loop {}");
+        let mut expected = vec![(
+            Limits {
+                timeout: Some(0.2),
+                ..Limits::default()
+            },
+            Limit::Timeout(0.2),
+        )];
+        // the CPU time of a thread is only known on unix
+        #[cfg(unix)]
+        expected.push((
+            Limits {
+                cpu_time: Some(0.2),
+                ..Limits::default()
+            },
+            Limit::CpuTime(0.2),
+        ));
+        for (limits, expected) in expected {
+            manager.set_limits(limits);
+            match manager.run_all_operations("abc", "python3") {
+                Err(Error::LimitExceeded { id, limit, .. }) => {
+                    assert_eq!(id, 1);
+                    assert_eq!(limit, expected);
+                }
+                r => panic!("Expected LimitExceeded, got {:?}", r),
+            }
+        }
+
+        write_opt(temp_dir.path(), 1, "let s = \"a\";\nloop { s += s; }");
+        manager.set_limits(Limits {
+            memory_mb: Some(1),
+            ..Limits::default()
+        });
+        match manager.run_all_operations("abc", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 1 (opt-1.rhai) exceeded its memory limit of 1 MiB"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+    }
+}
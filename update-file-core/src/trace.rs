@@ -0,0 +1,225 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::core::{char_index, OperationOutput};
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ValueChange {
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct DataMapDelta {
+    pub added: BTreeMap<String, String>,
+    pub changed: BTreeMap<String, ValueChange>,
+    pub removed: BTreeMap<String, String>,
+}
+
+impl DataMapDelta {
+    pub fn new(old: &HashMap<String, String>, new: &HashMap<String, String>) -> DataMapDelta {
+        let mut delta = DataMapDelta::default();
+        for (key, value) in new {
+            match old.get(key) {
+                None => {
+                    delta.added.insert(key.clone(), value.clone());
+                }
+                Some(old_value) if old_value != value => {
+                    delta.changed.insert(
+                        key.clone(),
+                        ValueChange {
+                            old: old_value.clone(),
+                            new: value.clone(),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        for (key, value) in old {
+            if !new.contains_key(key) {
+                delta.removed.insert(key.clone(), value.clone());
+            }
+        }
+        delta
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// State change caused by a single operation.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TraceStep {
+    pub id: usize,
+    pub file: String,
+    pub content_index: usize,
+    pub added_content: String,
+    pub data_map_delta: DataMapDelta,
+    pub error_message: String,
+}
+
+impl TraceStep {
+    /// The step of an operation run on `full_content`, with `content_index`
+    /// in code points like the operations count it.
+    pub fn new(
+        id: usize,
+        file: &str,
+        full_content: &str,
+        prev: &OperationOutput,
+        output: &OperationOutput,
+    ) -> TraceStep {
+        let added_content = output
+            .new_content
+            .strip_prefix(prev.new_content.as_str())
+            .unwrap_or(&output.new_content)
+            .to_string();
+        TraceStep {
+            id,
+            file: file.to_string(),
+            content_index: char_index(full_content, output.content_index),
+            added_content,
+            data_map_delta: DataMapDelta::new(&prev.data_map, &output.data_map),
+            error_message: output.error_message.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TraceFailure {
+    pub id: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    pub failure: Option<TraceFailure>,
+}
+
+impl Trace {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut content_index = 0;
+        for step in &self.steps {
+            writeln!(f, "Step {} ({})", step.id, step.file)?;
+            writeln!(
+                f,
+                "  content_index: {} -> {}",
+                content_index, step.content_index
+            )?;
+            content_index = step.content_index;
+            if step.added_content.is_empty() {
+                writeln!(f, "  new_content: (unchanged)")?;
+            } else {
+                writeln!(f, "  new_content: +{:?}", step.added_content)?;
+            }
+            let delta = &step.data_map_delta;
+            if delta.is_empty() {
+                writeln!(f, "  data_map: (unchanged)")?;
+            } else {
+                writeln!(f, "  data_map:")?;
+                for (key, value) in &delta.added {
+                    writeln!(f, "    + {} = {:?}", key, value)?;
+                }
+                for (key, change) in &delta.changed {
+                    writeln!(f, "    ~ {}: {:?} -> {:?}", key, change.old, change.new)?;
+                }
+                for (key, value) in &delta.removed {
+                    writeln!(f, "    - {} (was {:?})", key, value)?;
+                }
+            }
+            if !step.error_message.is_empty() {
+                writeln!(f, "  error_message: {}", step.error_message)?;
+            }
+        }
+        if let Some(failure) = &self.failure {
+            writeln!(f, "Step {} failed: {}", failure.id, failure.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_data_map_delta() {
+        let old = map(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let new = map(&[("a", "1"), ("b", "20"), ("d", "4")]);
+        let delta = DataMapDelta::new(&old, &new);
+        assert_eq!(
+            delta.added,
+            BTreeMap::from([("d".to_string(), "4".to_string())])
+        );
+        assert_eq!(
+            delta.changed,
+            BTreeMap::from([(
+                "b".to_string(),
+                ValueChange {
+                    old: "2".to_string(),
+                    new: "20".to_string()
+                }
+            )])
+        );
+        assert_eq!(
+            delta.removed,
+            BTreeMap::from([("c".to_string(), "3".to_string())])
+        );
+        assert!(DataMapDelta::new(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_trace_report() {
+        let prev = OperationOutput {
+            data_map: HashMap::new(),
+            content_index: 0,
+            new_content: "".to_string(),
+            error_message: "".to_string(),
+            error_step: None,
+        };
+        let output = OperationOutput {
+            data_map: map(&[("start", "Hello")]),
+            // "héllo\n" is 7 bytes
+            content_index: 7,
+            new_content: "hello\n".to_string(),
+            error_message: "".to_string(),
+            error_step: None,
+        };
+        let trace = Trace {
+            steps: vec![TraceStep::new(
+                1,
+                "opt-1.py",
+                "héllo\nworld",
+                &prev,
+                &output,
+            )],
+            failure: None,
+        };
+        assert_eq!(
+            trace.to_string(),
+            "Step 1 (opt-1.py)\n  content_index: 0 -> 6\n  new_content: +\"hello\\n\"\n  data_map:\n    + start = \"Hello\"\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        assert_eq!(json["steps"][0]["added_content"], "hello\n");
+        assert_eq!(
+            json["steps"][0]["data_map_delta"]["added"]["start"],
+            "Hello"
+        );
+    }
+}
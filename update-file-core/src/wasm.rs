@@ -0,0 +1,378 @@
+use std::fs;
+use wasmi::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TrapCode,
+    TypedFunc, TypedResumableCall, WasmParams, WasmResults,
+};
+
+use crate::core::{decode_output, encode_data, Operation, OperationData, OperationOutput};
+use crate::error::Error;
+use crate::limits::{Budget, Limit, Limits};
+use crate::runtime::{run_each, RunContext, Runtime};
+
+/// Extension of operation files compiled to WebAssembly.
+pub const WASM_EXTENSION: &str = "wasm";
+
+/// Runs `.wasm` operations in an embedded WebAssembly engine. A module gets
+/// nothing from the host: modules with imports are refused, so an operation
+/// cannot reach the filesystem, the network or the environment. It must
+/// export:
+///
+/// ```wat
+/// (memory (export "memory") 1)
+/// ;; a buffer of `len` bytes, to write the input to
+/// (func (export "alloc") (param $len i32) (result i32) ...)
+/// ;; runs on the `OperationData` JSON at `ptr`, returning where the
+/// ;; `OperationOutput` JSON is as `out_ptr << 32 | out_len`
+/// (func (export "run") (param $ptr i32) (param $len i32) (result i64) ...)
+/// ```
+///
+/// The timeout and CPU time limit of an operation are checked as it runs; its
+/// memory limit caps the size of its memory.
+pub struct WasmRuntime;
+
+/// Fuel a module runs on between two checks of the time limits, roughly one
+/// unit per instruction.
+const FUEL_INTERVAL: u64 = 1_000_000;
+
+fn invalid(opt: &Operation, message: String) -> Error {
+    Error::InvalidOperation {
+        id: opt.id(),
+        file: opt.file_name().to_string(),
+        message,
+    }
+}
+
+fn trapped(opt: &Operation, error: wasmi::Error) -> Error {
+    Error::OperationTrapped {
+        id: opt.id(),
+        file: opt.file_name().to_string(),
+        message: error.to_string(),
+    }
+}
+
+fn exceeded(opt: &Operation, limit: Limit) -> Error {
+    Error::LimitExceeded {
+        id: opt.id(),
+        file: opt.file_name().to_string(),
+        limit,
+    }
+}
+
+/// Call `func`, stopping it when it runs past a time limit of `budget`.
+fn call<Params, Results>(
+    opt: &Operation,
+    limits: Limits,
+    budget: &Budget,
+    store: &mut Store<StoreLimits>,
+    func: &TypedFunc<Params, Results>,
+    params: Params,
+) -> Result<Results, Error>
+where
+    Params: WasmParams,
+    Results: WasmResults,
+{
+    let trapped = |error: wasmi::Error| match (error.as_trap_code(), limits.memory_mb) {
+        (Some(TrapCode::GrowthOperationLimited), Some(mb)) => exceeded(opt, Limit::Memory(mb)),
+        _ => trapped(opt, error),
+    };
+    let mut call = func.call_resumable(&mut *store, params).map_err(trapped)?;
+    loop {
+        call = match call {
+            TypedResumableCall::Finished(results) => return Ok(results),
+            TypedResumableCall::OutOfFuel(invocation) => {
+                if let Some(limit) = budget.exceeded() {
+                    return Err(exceeded(opt, limit));
+                }
+                store
+                    .set_fuel(FUEL_INTERVAL.max(invocation.required_fuel()))
+                    .map_err(trapped)?;
+                invocation.resume(&mut *store).map_err(trapped)?
+            }
+            // nothing is imported, so no host function can trap
+            TypedResumableCall::HostTrap(_) => unreachable!("no host functions"),
+        };
+    }
+}
+
+fn instantiate(opt: &Operation, store: &mut Store<StoreLimits>) -> Result<Instance, Error> {
+    let wasm = fs::read(opt.path()).map_err(|_| Error::OperationNotFound { id: opt.id() })?;
+    let module = Module::new(store.engine(), wasm).map_err(|e| invalid(opt, e.to_string()))?;
+    if let Some(import) = module.imports().next() {
+        return Err(invalid(
+            opt,
+            format!(
+                "Imports are not allowed, the module imports {}.{}",
+                import.module(),
+                import.name()
+            ),
+        ));
+    }
+    Instance::new(&mut *store, &module, &[]).map_err(|e| trapped(opt, e))
+}
+
+fn run_module(
+    opt: &Operation,
+    data: &OperationData,
+    context: &RunContext,
+) -> Result<OperationOutput, Error> {
+    let limits = opt.limits().or(context.limits);
+    let budget = Budget::start(limits);
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let mut store_limits = StoreLimitsBuilder::new().trap_on_grow_failure(true);
+    if let Some(mb) = limits.memory_mb {
+        store_limits = store_limits
+            .memory_size(usize::try_from(mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX));
+    }
+    let mut store = Store::new(&engine, store_limits.build());
+    store.limiter(|store_limits| store_limits);
+    store.set_fuel(FUEL_INTERVAL).map_err(|e| trapped(opt, e))?;
+    let binary = context.binary;
+    let instance = instantiate(opt, &mut store)?;
+    let memory: Memory = instance
+        .get_memory(&store, "memory")
+        .ok_or_else(|| invalid(opt, "The module does not export memory".to_string()))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "alloc")
+        .map_err(|e| invalid(opt, e.to_string()))?;
+    let run = instance
+        .get_typed_func::<(i32, i32), i64>(&store, "run")
+        .map_err(|e| invalid(opt, e.to_string()))?;
+
+    let input = encode_data(data, binary)?.into_bytes();
+    let len = i32::try_from(input.len())
+        .map_err(|_| invalid(opt, "The input does not fit in memory".to_string()))?;
+    let ptr = call(opt, limits, &budget, &mut store, &alloc, len)?;
+    memory
+        .write(&mut store, ptr as u32 as usize, &input)
+        .map_err(|e| invalid(opt, format!("Input buffer at {}: {}", ptr, e)))?;
+    let result = call(opt, limits, &budget, &mut store, &run, (ptr, len))? as u64;
+
+    let (out_ptr, out_len) = ((result >> 32) as usize, (result & 0xffff_ffff) as usize);
+    // read in place, the module tells where the output is
+    let output = memory
+        .data(&store)
+        .get(out_ptr..out_ptr.saturating_add(out_len))
+        .ok_or_else(|| {
+            invalid(
+                opt,
+                format!(
+                    "Output of {} bytes at {} is out of memory",
+                    out_len, out_ptr
+                ),
+            )
+        })?;
+    let output = std::str::from_utf8(output).map_err(|e| invalid(opt, e.to_string()))?;
+    decode_output(output, data.full_content, binary, Some(opt.id()))
+}
+
+impl Runtime for WasmRuntime {
+    fn extension(&self) -> &str {
+        WASM_EXTENSION
+    }
+
+    fn run(
+        &self,
+        opts: &[Operation],
+        data: &OperationData,
+        context: &RunContext,
+    ) -> Result<OperationOutput, Error> {
+        run_each(opts, data, |opt, data| run_module(opt, data, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationManager;
+    use tempfile::tempdir;
+
+    /// A module answering `output` to any input starting with `{`.
+    fn module(output: &str, extra: &str) -> Vec<u8> {
+        let escaped = output.replace('\\', "\\\\").replace('"', "\\\"");
+        wat::parse_str(format!(
+            r#"(module
+                {extra}
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{escaped}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    i32.const 1024)
+                (func (export "run") (param $ptr i32) (param $len i32) (result i64)
+                    (if (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 123))
+                        (then unreachable))
+                    i64.const {len}))"#,
+            len = output.len()
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_wasm_runtime() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let path = temp_dir.path().join("opt-1.wasm");
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        let output = r#"{"data_map": {"wasm": "yes"}, "content_index": 5, "new_content": "HELLO", "error_message": ""}"#;
+        fs::write(&path, module(output, "")).unwrap();
+        let result = manager.run_all_operations("Hello", "python3").unwrap();
+        assert_eq!(result.new_content, "HELLO");
+        assert_eq!(result.data_map["wasm"], "yes");
+
+        let output =
+            r#"{"data_map": {}, "content_index": 0, "new_content": "", "error_message": "no"}"#;
+        fs::write(&path, module(output, "")).unwrap();
+        match manager.run_all_operations("Hello", "python3") {
+            Err(Error::OperationReported { id, message }) => {
+                assert_eq!(id, Some(1));
+                assert_eq!(message, "no");
+            }
+            r => panic!("Expected OperationReported, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_wasm_runtime_errors() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let path = temp_dir.path().join("opt-1.wasm");
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        fs::write(
+            &path,
+            module("{}", r#"(import "env" "read_file" (func $read_file))"#),
+        )
+        .unwrap();
+        match manager.run_all_operations("Hello", "python3") {
+            Err(Error::InvalidOperation { id, message, .. }) => {
+                assert_eq!(id, 1);
+                assert_eq!(
+                    message,
+                    "Imports are not allowed, the module imports env.read_file"
+                );
+            }
+            r => panic!("Expected InvalidOperation, got {:?}", r),
+        }
+
+        fs::write(&path, "not a module").unwrap();
+        assert!(matches!(
+            manager.run_all_operations("Hello", "python3"),
+            Err(Error::InvalidOperation { .. })
+        ));
+
+        let trap = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "run") (param i32 i32) (result i64) unreachable))"#,
+        )
+        .unwrap();
+        fs::write(&path, trap).unwrap();
+        match manager.run_all_operations("Hello", "python3") {
+            Err(e @ Error::OperationTrapped { .. }) => {
+                assert!(e.to_string().starts_with("Step 1 (opt-1.wasm) trapped: "))
+            }
+            r => panic!("Expected OperationTrapped, got {:?}", r),
+        }
+
+        let out_of_bounds = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "run") (param i32 i32) (result i64)
+                    i64.const 0x0010000000000010))"#,
+        )
+        .unwrap();
+        fs::write(&path, out_of_bounds).unwrap();
+        assert!(matches!(
+            manager.run_all_operations("Hello", "python3"),
+            Err(Error::InvalidOperation { .. })
+        ));
+
+        // a length of nearly 4 GiB is refused before anything is allocated
+        let too_long = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "run") (param i32 i32) (result i64)
+                    i64.const 0x00000000ffffffff))"#,
+        )
+        .unwrap();
+        fs::write(&path, too_long).unwrap();
+        match manager.run_all_operations("Hello", "python3") {
+            Err(Error::InvalidOperation { message, .. }) => {
+                assert_eq!(message, "Output of 4294967295 bytes at 0 is out of memory")
+            }
+            r => panic!("Expected InvalidOperation, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_wasm_runtime_limits() {
+        let temp_dir = tempdir().unwrap();
+        let opt_dir_path = temp_dir.path().to_str().unwrap();
+        let path = temp_dir.path().join("opt-1.wasm");
+        let mut manager = OperationManager::new(opt_dir_path);
+
+        let endless = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "run") (param i32 i32) (result i64)
+                    (loop $forever (br $forever))
+                    i64.const 0))"#,
+        )
+        .unwrap();
+        fs::write(&path, endless).unwrap();
+        let mut expected = vec![(
+            Limits {
+                timeout: Some(0.2),
+                ..Limits::default()
+            },
+            Limit::Timeout(0.2),
+        )];
+        // the CPU time of a thread is only known on unix
+        #[cfg(unix)]
+        expected.push((
+            Limits {
+                cpu_time: Some(0.2),
+                ..Limits::default()
+            },
+            Limit::CpuTime(0.2),
+        ));
+        for (limits, expected) in expected {
+            manager.set_limits(limits);
+            match manager.run_all_operations("Hello", "python3") {
+                Err(Error::LimitExceeded { id, limit, .. }) => {
+                    assert_eq!(id, 1);
+                    assert_eq!(limit, expected);
+                }
+                r => panic!("Expected LimitExceeded, got {:?}", r),
+            }
+        }
+
+        // 64 pages are 4 MiB
+        let growing = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "run") (param i32 i32) (result i64)
+                    (drop (memory.grow (i32.const 64)))
+                    i64.const 0))"#,
+        )
+        .unwrap();
+        fs::write(&path, growing).unwrap();
+        manager.set_limits(Limits {
+            memory_mb: Some(1),
+            ..Limits::default()
+        });
+        match manager.run_all_operations("Hello", "python3") {
+            Err(e @ Error::LimitExceeded { .. }) => assert_eq!(
+                e.to_string(),
+                "Step 1 (opt-1.wasm) exceeded its memory limit of 1 MiB"
+            ),
+            r => panic!("Expected LimitExceeded, got {:?}", r),
+        }
+    }
+}
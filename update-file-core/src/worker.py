@@ -0,0 +1,49 @@
+#!/usr/bin/env python3
+
+# Long-lived runner: loads the pipeline once and runs it for many inputs.
+#
+# The first line of stdin is the pipeline code as a JSON string. Every line
+# after it is an OperationData, answered by one line on stdout, either
+# {"output": OperationOutput} or {"error": traceback}.
+
+import io
+import linecache
+import os
+import sys
+import traceback
+from json import dumps, loads
+
+SCRIPT_NAME = "<pipeline>"
+
+# move the protocol off fds 0 and 1, so operations writing to fd 1 directly
+# or through a child process end up on stderr instead of corrupting it
+protocol_in = os.fdopen(os.dup(0), "r")
+protocol_out = os.fdopen(os.dup(1), "w")
+os.dup2(2, 1)
+devnull = os.open(os.devnull, os.O_RDONLY)
+os.dup2(devnull, 0)
+os.close(devnull)
+sys.stdin = io.StringIO()
+sys.stdout = sys.stderr
+
+code = loads(protocol_in.readline())
+# keep the source lines in tracebacks, like a script run from a file
+linecache.cache[SCRIPT_NAME] = (len(code), None, code.splitlines(True), SCRIPT_NAME)
+pipeline = compile(code, SCRIPT_NAME, "exec")
+
+for line in protocol_in:
+    if not line.strip():
+        continue
+    # every run starts from fresh globals, nothing leaks between inputs
+    scope = {"__name__": "__main__"}
+    # the operations must not read or write the protocol streams
+    sys.stdin = io.StringIO()
+    sys.stdout = sys.stderr
+    try:
+        scope["json_in"] = loads(line)
+        exec(pipeline, scope)
+        reply = {"output": scope["output"]}
+    except BaseException:
+        reply = {"error": traceback.format_exc()}
+    protocol_out.write(dumps(reply) + "\n")
+    protocol_out.flush()
@@ -1,14 +1,15 @@
-mod utils;
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, Error, LitStr};
-use utils::get_content;
+use update_file_utils::get_content;
 
+/// Embed the part of a file under the calling crate's `src/` between `start` and
+/// the optional `end` pattern as a string literal.
 #[proc_macro]
 pub fn get_content_const(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as GetContentInput);
-    let prefix_src = "src/";
-    let file_path = format!("{}{}", prefix_src, input.file_path.value());
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
+    let file_path = format!("{}/src/{}", manifest_dir, input.file_path.value());
     let content = match std::fs::read_to_string(file_path) {
         Ok(content) => content,
         Err(err) => {